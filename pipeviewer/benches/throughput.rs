@@ -0,0 +1,92 @@
+//! Compares allocating a `Vec` per chunk with recycling pooled buffers.
+//!
+//! Run with `cargo bench --bench throughput`.
+
+use crossbeam::channel::bounded;
+use pipeviewer::pool::{BufferPool, Chunk};
+use pipeviewer::read::Input;
+use pipeviewer::write::Output;
+use pipeviewer::{Pipeline, DEFAULT_BUFFER_SIZE, DEFAULT_QUEUE_DEPTH};
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TOTAL: u64 = 4 * 1024 * 1024 * 1024;
+
+fn report(name: &str, bytes: u64, elapsed: Duration) {
+    let rate = bytes as f64 / elapsed.as_secs_f64() / (1024.0 * 1024.0 * 1024.0);
+    println!("{:<40} {:>8.2} GiB/s", name, rate);
+}
+
+/// The previous read loop: a stack buffer copied into a new `Vec` for every chunk
+fn allocating(buffer_size: usize) -> Duration {
+    let (tx, rx) = bounded::<Arc<Vec<u8>>>(DEFAULT_QUEUE_DEPTH);
+    let consumer = thread::spawn(move || rx.iter().map(|chunk| chunk.len()).sum::<usize>());
+    let start = Instant::now();
+    let mut reader = io::repeat(7).take(TOTAL);
+    let mut buffer = vec![0; buffer_size];
+    loop {
+        let num_read = reader.read(&mut buffer).unwrap();
+        if num_read == 0 {
+            break;
+        }
+        tx.send(Arc::new(Vec::from(&buffer[..num_read]))).unwrap();
+    }
+    drop(tx);
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+/// The pooled read loop: reading straight into a recycled buffer
+fn pooled(buffer_size: usize) -> Duration {
+    let (tx, rx) = bounded(DEFAULT_QUEUE_DEPTH);
+    let consumer = thread::spawn(move || rx.iter().map(|chunk: Chunk| chunk.len()).sum::<usize>());
+    let start = Instant::now();
+    let pool = BufferPool::new(buffer_size, DEFAULT_QUEUE_DEPTH);
+    let mut reader = io::repeat(7).take(TOTAL);
+    loop {
+        let mut buffer = pool.take();
+        let num_read = reader.read(buffer.space()).unwrap();
+        if num_read == 0 {
+            break;
+        }
+        buffer.set_len(num_read);
+        tx.send(Arc::new(buffer)).unwrap();
+    }
+    drop(tx);
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+fn pipeline(buffer_size: usize) -> Duration {
+    let start = Instant::now();
+    Pipeline::new()
+        .input(Input::new("repeat", io::repeat(7).take(TOTAL)))
+        .output(Output::new("sink", io::sink()))
+        .buffer_size(buffer_size)
+        .run()
+        .unwrap();
+    start.elapsed()
+}
+
+fn main() {
+    for &buffer_size in &[DEFAULT_BUFFER_SIZE, 128 * 1024, 1024 * 1024] {
+        let kib = buffer_size / 1024;
+        report(
+            &format!("allocating, {} KiB chunks", kib),
+            TOTAL,
+            allocating(buffer_size),
+        );
+        report(
+            &format!("pooled, {} KiB chunks", kib),
+            TOTAL,
+            pooled(buffer_size),
+        );
+        report(
+            &format!("pipeline, {} KiB chunks", kib),
+            TOTAL,
+            pipeline(buffer_size),
+        );
+    }
+}
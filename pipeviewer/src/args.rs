@@ -0,0 +1,476 @@
+use clap::{App, Arg, ErrorKind};
+use pipeviewer::checksum::Algorithm;
+use pipeviewer::compress::Compression;
+use pipeviewer::remote::Command;
+use pipeviewer::template::Template;
+use pipeviewer::transform::{self, Transform};
+use pipeviewer::units::{parse_duration, parse_size, UnitSystem, Units};
+use std::env;
+use std::time::Duration;
+
+/// How the progress is reported on stderr
+pub enum Format {
+    Terminal(Template),
+    Json,
+    Numeric,
+}
+
+pub struct Args {
+    pub infiles: Vec<String>,
+    pub outfiles: Vec<String>,
+    pub drop_failed: bool,
+    pub silent: bool,
+    pub format: Format,
+    pub rate_limit: Option<usize>,
+    pub size: Option<usize>,
+    pub line_mode: bool,
+    pub skip: Option<usize>,
+    pub stop_at_size: bool,
+    pub skip_errors: bool,
+    pub checksum: Option<Algorithm>,
+    pub checksum_file: Option<String>,
+    pub buffer_size: Option<usize>,
+    pub queue_depth: Option<usize>,
+    pub no_splice: bool,
+    pub compression: Option<Compression>,
+    pub transforms: Vec<Box<dyn Transform>>,
+    pub watchfd: Option<(u32, Option<u32>)>,
+    pub cursor: bool,
+    pub name: Option<String>,
+    pub units: Units,
+    pub stall_timeout: Option<Duration>,
+    pub metrics_addr: Option<String>,
+    /// Address to take the input from over TCP instead of the infiles
+    pub listen: Option<String>,
+    /// Address to send the output to over TCP, next to the outfiles
+    pub connect: Option<String>,
+    /// Process to send `commands` to instead of copying anything
+    pub remote: Option<u32>,
+    pub commands: Vec<Command>,
+}
+
+/// Parses the `PID[:FD]` argument of `--watchfd`
+fn parse_watchfd(value: &str) -> Result<(u32, Option<u32>), String> {
+    let invalid = |_| format!("expected PID or PID:FD, got `{}`", value);
+    match value.split_once(':') {
+        Some((pid, fd)) => Ok((
+            pid.parse().map_err(invalid)?,
+            Some(fd.parse().map_err(invalid)?),
+        )),
+        None => Ok((value.parse().map_err(invalid)?, None)),
+    }
+}
+
+impl Args {
+    pub fn new() -> Self {
+        let matches = App::new("pipeviewer")
+            .arg(
+                Arg::with_name("infile")
+                    .multiple(true)
+                    .help("Read from files (concatenated in order) instead of stdin"),
+            )
+            .arg(
+                Arg::with_name("outfile")
+                    .short("o")
+                    .long("outfile")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Write output to a file instead of stdout, repeat to tee (- is stdout)"),
+            )
+            .arg(
+                Arg::with_name("drop_failed").long("drop-failed").help(
+                    "Drop an output that fails (e.g. broken pipe) and keep writing the others",
+                ),
+            )
+            .arg(
+                Arg::with_name("silent")
+                    .short("s")
+                    .long("silent")
+                    .help("Silences the output"),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .short("F")
+                    .long("format")
+                    .takes_value(true)
+                    .default_value("terminal")
+                    .validator(|v| match v.as_str() {
+                        "terminal" | "json" | "numeric" => Ok(()),
+                        template => template
+                            .parse::<Template>()
+                            .map(|_| ())
+                            .map_err(|e| e.to_string()),
+                    })
+                    .help(
+                        "Progress output: terminal, json, numeric or a terminal line template \
+                         with %N name, %I input, %b bytes, %t timer, %r rate, %a average rate, \
+                         %p progress, %l left, %e ETA",
+                    ),
+            )
+            .arg(
+                Arg::with_name("numeric")
+                    .short("n")
+                    .long("numeric")
+                    .help("Same as --format numeric, e.g. for dialog --gauge"),
+            )
+            .arg(
+                Arg::with_name("rate_limit")
+                    .short("L")
+                    .long("rate-limit")
+                    .takes_value(true)
+                    .value_name("RATE")
+                    .validator(|v| parse_size(&v).map(|_| ()))
+                    .help("Limit the transfer to RATE bytes per second (e.g. 512K, 5M), 0 for none"),
+            )
+            .arg(
+                Arg::with_name("size")
+                    .long("size")
+                    .takes_value(true)
+                    .value_name("SIZE")
+                    .validator(|v| parse_size(&v).map(|_| ()))
+                    .help("Expected total size, used for the progress bar and ETA"),
+            )
+            .arg(
+                Arg::with_name("skip")
+                    .long("skip")
+                    .takes_value(true)
+                    .value_name("SIZE")
+                    .validator(|v| parse_size(&v).map(|_| ()))
+                    .help("Skip the first SIZE bytes of the input, seeking in regular files"),
+            )
+            .arg(
+                Arg::with_name("stop_at_size")
+                    .short("S")
+                    .long("stop-at-size")
+                    .requires("size")
+                    .conflicts_with("line_mode")
+                    .help("Stop after --size bytes"),
+            )
+            .arg(
+                Arg::with_name("skip_errors")
+                    .short("E")
+                    .long("skip-errors")
+                    .help("Keep reading past read errors in files, writing zeros for the bad blocks"),
+            )
+            .arg(
+                Arg::with_name("line_mode")
+                    .short("l")
+                    .long("line-mode")
+                    .help("Count lines instead of bytes (--size is then a number of lines)"),
+            )
+            .arg(
+                Arg::with_name("si")
+                    .long("si")
+                    .help("Show sizes in powers of 1000 (kB, MB) instead of 1024 (KiB, MiB)"),
+            )
+            .arg(
+                Arg::with_name("bits")
+                    .long("bits")
+                    .help("Show rates in bits per second"),
+            )
+            .arg(
+                Arg::with_name("precision")
+                    .long("precision")
+                    .takes_value(true)
+                    .value_name("DIGITS")
+                    .default_value("1")
+                    .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help("Number of decimals in sizes and rates"),
+            )
+            .arg(
+                Arg::with_name("checksum")
+                    .long("checksum")
+                    .takes_value(true)
+                    .possible_values(&["crc32", "sha256", "blake3"])
+                    .help("Compute a checksum of the data passing through"),
+            )
+            .arg(
+                Arg::with_name("checksum_file")
+                    .long("checksum-file")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .requires("checksum")
+                    .help("Write the checksum to PATH instead of printing it"),
+            )
+            .arg(
+                Arg::with_name("compress")
+                    .long("compress")
+                    .takes_value(true)
+                    .possible_values(&["gzip", "zstd"])
+                    .conflicts_with("decompress")
+                    .help("Compress the data before writing it"),
+            )
+            .arg(
+                Arg::with_name("decompress")
+                    .long("decompress")
+                    .takes_value(true)
+                    .possible_values(&["auto", "gzip", "zstd"])
+                    .help("Decompress the data before writing it, auto detects the format"),
+            )
+            .arg(
+                Arg::with_name("transform")
+                    .short("t")
+                    .long("transform")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("NAME")
+                    .validator(|v| transform::from_spec(&v).map(|_| ()))
+                    .help(
+                        "Filter the data, repeat to chain: upper, hexdump, base64, base64-decode \
+                         or tr:FROM:TO",
+                    ),
+            )
+            .arg(
+                Arg::with_name("cursor")
+                    .short("c")
+                    .long("cursor")
+                    .help("Draw on a row of its own, for several pipeviewers in one pipeline"),
+            )
+            .arg(
+                Arg::with_name("name")
+                    .short("N")
+                    .long("name")
+                    .takes_value(true)
+                    .help("Prefix the progress with NAME, useful with --cursor"),
+            )
+            .arg(
+                Arg::with_name("watchfd")
+                    .short("d")
+                    .long("watchfd")
+                    .takes_value(true)
+                    .value_name("PID[:FD]")
+                    .conflicts_with_all(&["infile", "outfile"])
+                    .validator(|v| parse_watchfd(&v).map(|_| ()))
+                    .help("Watch the files open in process PID (or only descriptor FD) instead"),
+            )
+            .arg(
+                Arg::with_name("stall_timeout")
+                    .long("stall-timeout")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .conflicts_with("watchfd")
+                    .validator(|v| parse_duration(&v).map(|_| ()))
+                    .help("Give up with exit status 124 when no data flows for DURATION (e.g. 30s, 5m)"),
+            )
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .takes_value(true)
+                    .value_name("[ADDR:]PORT")
+                    .conflicts_with_all(&["infile", "watchfd", "remote"])
+                    .help("Read from the first TCP connection made to PORT instead of stdin"),
+            )
+            .arg(
+                Arg::with_name("connect")
+                    .long("connect")
+                    .takes_value(true)
+                    .value_name("HOST:PORT")
+                    .conflicts_with_all(&["watchfd", "remote"])
+                    .help("Send the output to HOST:PORT over TCP instead of stdout"),
+            )
+            .arg(
+                Arg::with_name("metrics_addr")
+                    .long("metrics-addr")
+                    .takes_value(true)
+                    .value_name("ADDR")
+                    .conflicts_with("watchfd")
+                    .help(
+                        "Serve the progress in Prometheus format at http://ADDR/metrics \
+                         (e.g. 127.0.0.1:9100)",
+                    ),
+            )
+            .arg(
+                Arg::with_name("remote")
+                    .short("R")
+                    .long("remote")
+                    .takes_value(true)
+                    .value_name("PID")
+                    .conflicts_with_all(&["infile", "outfile", "watchfd"])
+                    .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help(
+                        "Change the rate limit (-L, 0 lifts it) or template (-F) of the \
+                         pipeviewer running as PID, or pause and resume it",
+                    ),
+            )
+            .arg(
+                Arg::with_name("pause")
+                    .long("pause")
+                    .requires("remote")
+                    .conflicts_with("resume")
+                    .help("With --remote, stop reading until --resume"),
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .requires("remote")
+                    .help("With --remote, continue after --pause"),
+            )
+            .arg(
+                Arg::with_name("buffer_size")
+                    .short("B")
+                    .long("buffer-size")
+                    .takes_value(true)
+                    .value_name("SIZE")
+                    .validator(|v| parse_size(&v).map(|_| ()))
+                    .help("Read at most SIZE bytes at once (default 16K)"),
+            )
+            .arg(
+                Arg::with_name("queue_depth")
+                    .long("queue-depth")
+                    .takes_value(true)
+                    .value_name("CHUNKS")
+                    .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+                    .help(
+                        "Number of chunks that may queue up in front of each stage (default 1024)",
+                    ),
+            )
+            .arg(
+                Arg::with_name("no_splice")
+                    .long("no-splice")
+                    .help("Always copy through pipeviewer's buffers, never with splice(2)"),
+            )
+            .get_matches();
+        let infiles = matches
+            .values_of("infile")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let outfiles = matches
+            .values_of("outfile")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        let drop_failed = matches.is_present("drop_failed");
+        let silent = if matches.is_present("silent") {
+            true
+        } else {
+            !env::var("PV_SILENT").unwrap_or_default().is_empty()
+        };
+        let format = match matches.value_of("format") {
+            _ if matches.is_present("numeric") => Format::Numeric,
+            Some("json") => Format::Json,
+            Some("numeric") => Format::Numeric,
+            Some("terminal") | None => Format::Terminal(Template::default()),
+            Some(template) => Format::Terminal(template.parse().unwrap_or_default()),
+        };
+        let rate_limit = matches
+            .value_of("rate_limit")
+            .and_then(|v| parse_size(v).ok())
+            // no rate at all means no limit, as with the remote command
+            .filter(|&rate| rate > 0);
+        let size = matches.value_of("size").and_then(|v| parse_size(v).ok());
+        let line_mode = matches.is_present("line_mode");
+        let skip = matches.value_of("skip").and_then(|v| parse_size(v).ok());
+        let stop_at_size = matches.is_present("stop_at_size");
+        let skip_errors = matches.is_present("skip_errors");
+        let checksum = matches.value_of("checksum").and_then(|v| v.parse().ok());
+        let checksum_file = matches.value_of("checksum_file").map(String::from);
+        let buffer_size = matches
+            .value_of("buffer_size")
+            .and_then(|v| parse_size(v).ok());
+        let queue_depth = matches.value_of("queue_depth").and_then(|v| v.parse().ok());
+        let no_splice = matches.is_present("no_splice");
+        let compression = match (matches.value_of("compress"), matches.value_of("decompress")) {
+            (Some(codec), _) => codec.parse().ok().map(Compression::Compress),
+            (_, Some("auto")) => Some(Compression::Decompress(None)),
+            (_, Some(codec)) => codec.parse().ok().map(|c| Compression::Decompress(Some(c))),
+            (None, None) => None,
+        };
+        let watchfd = matches
+            .value_of("watchfd")
+            .and_then(|v| parse_watchfd(v).ok());
+        let stall_timeout = matches
+            .value_of("stall_timeout")
+            .and_then(|v| parse_duration(v).ok());
+        // a bare port listens on every interface
+        let listen = matches.value_of("listen").map(|v| match v.parse::<u16>() {
+            Ok(port) => format!("0.0.0.0:{}", port),
+            Err(_) => v.to_string(),
+        });
+        let connect = matches.value_of("connect").map(String::from);
+        let metrics_addr = matches.value_of("metrics_addr").map(String::from);
+        let remote = matches.value_of("remote").and_then(|v| v.parse().ok());
+        let mut commands = Vec::new();
+        if remote.is_some() {
+            if matches.occurrences_of("format") > 0 || matches.is_present("numeric") {
+                match &format {
+                    Format::Terminal(template) => commands.push(Command::Format(template.clone())),
+                    _ => clap::Error::with_description(
+                        "--remote can only switch to another terminal template",
+                        ErrorKind::InvalidValue,
+                    )
+                    .exit(),
+                }
+            }
+            if matches.is_present("rate_limit") {
+                commands.push(Command::RateLimit(rate_limit));
+            }
+            if matches.is_present("pause") {
+                commands.push(Command::Pause);
+            }
+            if matches.is_present("resume") {
+                commands.push(Command::Resume);
+            }
+            if commands.is_empty() {
+                clap::Error::with_description(
+                    "nothing to send, --remote takes -L, -F, --pause or --resume",
+                    ErrorKind::MissingRequiredArgument,
+                )
+                .exit()
+            }
+        }
+        let cursor = matches.is_present("cursor");
+        let name = matches.value_of("name").map(String::from);
+        let units = Units::default()
+            .system(if matches.is_present("si") {
+                UnitSystem::Si
+            } else {
+                UnitSystem::Iec
+            })
+            .precision(
+                matches
+                    .value_of("precision")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1),
+            )
+            .bits(matches.is_present("bits"));
+        let transforms = matches
+            .values_of("transform")
+            .map(|values| {
+                values
+                    .filter_map(|v| transform::from_spec(v).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            infiles,
+            outfiles,
+            drop_failed,
+            silent,
+            format,
+            rate_limit,
+            size,
+            line_mode,
+            skip,
+            stop_at_size,
+            skip_errors,
+            checksum,
+            checksum_file,
+            buffer_size,
+            queue_depth,
+            no_splice,
+            compression,
+            transforms,
+            watchfd,
+            cursor,
+            name,
+            units,
+            stall_timeout,
+            metrics_addr,
+            listen,
+            connect,
+            remote,
+            commands,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Checksums of the transferred data, computed on the stats thread.
+
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Crc32,
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Crc32 => "crc32",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "crc32" => Ok(Algorithm::Crc32),
+            "sha256" => Ok(Algorithm::Sha256),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => Err(format!("unknown checksum algorithm '{}'", name)),
+        }
+    }
+}
+
+/// Incremental state of one of the algorithms
+pub enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// Lowercase hex digest of everything passed to `update`
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+            Hasher::Sha256(hasher) => to_hex(&hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, Hasher};
+
+    #[test]
+    fn digests_across_chunks() {
+        let pairs = vec![
+            (Algorithm::Crc32, "cbf43926"),
+            (
+                Algorithm::Sha256,
+                "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225",
+            ),
+            (
+                Algorithm::Blake3,
+                "b7d65b48420d1033cb2595293263b6f72eabee20d55e699d0df1973b3c9deed1",
+            ),
+        ];
+        for (algorithm, digest) in pairs {
+            let mut hasher = Hasher::new(algorithm);
+            hasher.update(b"1234");
+            hasher.update(b"56789");
+            assert_eq!(hasher.finalize(), digest, "{}", algorithm.name());
+        }
+    }
+
+    #[test]
+    fn algorithm_names() {
+        assert_eq!("SHA256".parse(), Ok(Algorithm::Sha256));
+        assert!("md5".parse::<Algorithm>().is_err());
+    }
+}
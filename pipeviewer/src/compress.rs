@@ -0,0 +1,240 @@
+//! gzip and zstd compression as a pipeline transform.
+
+use super::transform::Transform;
+use flate2::write::{GzEncoder, MultiGzDecoder};
+use std::fmt;
+use std::io::{self, ErrorKind, Write};
+use std::mem;
+use std::str::FromStr;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// Recognises a compressed stream by its first bytes
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&ZSTD_MAGIC) {
+            Some(Codec::Zstd)
+        } else if header.starts_with(&GZIP_MAGIC) {
+            Some(Codec::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Codec::Gzip),
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(format!("unknown compression format `{}`", s)),
+        }
+    }
+}
+
+/// What the compression stage does to the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Compress(Codec),
+    /// Decompresses, detecting the format from the first bytes when `None`
+    Decompress(Option<Codec>),
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Compress(codec) => write!(f, "{} compression", codec.name()),
+            Compression::Decompress(Some(codec)) => write!(f, "{} decompression", codec.name()),
+            Compression::Decompress(None) => write!(f, "decompression"),
+        }
+    }
+}
+
+/// Encoders and decoders writing into a `Vec` that is drained after every chunk
+enum Coder {
+    GzipEncoder(GzEncoder<Vec<u8>>),
+    GzipDecoder(MultiGzDecoder<Vec<u8>>),
+    ZstdEncoder(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    ZstdDecoder(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl Coder {
+    fn new(compress: bool, codec: Codec) -> io::Result<Self> {
+        Ok(match (compress, codec) {
+            (true, Codec::Gzip) => {
+                Coder::GzipEncoder(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            (false, Codec::Gzip) => Coder::GzipDecoder(MultiGzDecoder::new(Vec::new())),
+            // level 0 picks zstd's default level
+            (true, Codec::Zstd) => {
+                Coder::ZstdEncoder(zstd::stream::write::Encoder::new(Vec::new(), 0)?)
+            }
+            (false, Codec::Zstd) => {
+                Coder::ZstdDecoder(zstd::stream::write::Decoder::new(Vec::new())?)
+            }
+        })
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Coder::GzipEncoder(coder) => coder.write_all(data),
+            Coder::GzipDecoder(coder) => coder.write_all(data),
+            Coder::ZstdEncoder(coder) => coder.write_all(data),
+            Coder::ZstdDecoder(coder) => coder.write_all(data),
+        }
+    }
+
+    /// Takes the bytes produced so far
+    fn output(&mut self) -> Vec<u8> {
+        mem::take(match self {
+            Coder::GzipEncoder(coder) => coder.get_mut(),
+            Coder::GzipDecoder(coder) => coder.get_mut(),
+            Coder::ZstdEncoder(coder) => coder.get_mut(),
+            Coder::ZstdDecoder(coder) => coder.get_mut(),
+        })
+    }
+
+    /// Ends the stream and returns the remaining bytes
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Coder::GzipEncoder(coder) => coder.finish(),
+            Coder::GzipDecoder(coder) => coder.finish(),
+            Coder::ZstdEncoder(coder) => coder.finish(),
+            Coder::ZstdDecoder(mut coder) => {
+                coder.flush()?;
+                Ok(coder.into_inner())
+            }
+        }
+    }
+}
+
+/// A [`Transform`] compressing or decompressing the stream. When decompressing
+/// without a codec the first bytes are held back until the format is known.
+pub struct Compressor {
+    compression: Compression,
+    coder: Option<Coder>,
+    header: Vec<u8>,
+}
+
+impl Compressor {
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            compression,
+            coder: None,
+            header: Vec::new(),
+        }
+    }
+
+    /// Creates the coder on first use, returns `None` while the format is still unknown
+    fn coder(&mut self, data: &[u8], end: bool) -> io::Result<Option<&mut Coder>> {
+        if self.coder.is_some() {
+            return Ok(self.coder.as_mut());
+        }
+        let coder = match self.compression {
+            Compression::Compress(codec) => Coder::new(true, codec)?,
+            Compression::Decompress(Some(codec)) => Coder::new(false, codec)?,
+            Compression::Decompress(None) => {
+                self.header.extend_from_slice(data);
+                if self.header.len() < ZSTD_MAGIC.len() && !end {
+                    return Ok(None);
+                }
+                let codec = Codec::detect(&self.header).ok_or_else(|| {
+                    io::Error::new(ErrorKind::InvalidData, "unrecognised compression format")
+                })?;
+                self.compression = Compression::Decompress(Some(codec));
+                let mut coder = Coder::new(false, codec)?;
+                coder.write_all(&mem::take(&mut self.header))?;
+                self.coder = Some(coder);
+                return Ok(None);
+            }
+        };
+        self.coder = Some(coder);
+        Ok(self.coder.as_mut())
+    }
+}
+
+impl Transform for Compressor {
+    fn name(&self) -> String {
+        self.compression.to_string()
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        if let Some(coder) = self.coder(data, false)? {
+            coder.write_all(data)?;
+        }
+        if let Some(coder) = self.coder.as_mut() {
+            output.extend(coder.output());
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if self.compression == Compression::Decompress(None) && self.header.is_empty() {
+            // nothing to decompress, nothing to detect
+            return Ok(());
+        }
+        self.coder(&[], true)?;
+        if let Some(coder) = self.coder.take() {
+            output.extend(coder.finish()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, Compression, Compressor};
+    use crate::transform::Transform;
+
+    fn run(compression: Compression, input: &[u8]) -> Vec<u8> {
+        let mut compressor = Compressor::new(compression);
+        let mut output = Vec::new();
+        // odd piece sizes, so the format detection sees split headers
+        for piece in input.chunks(3) {
+            compressor.update(piece, &mut output).unwrap();
+        }
+        compressor.finish(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = b"pipeviewer ".repeat(1000);
+        for &codec in &[Codec::Gzip, Codec::Zstd] {
+            let compressed = run(Compression::Compress(codec), &data);
+            assert!(compressed.len() < data.len());
+            assert_eq!(Codec::detect(&compressed), Some(codec));
+            assert_eq!(run(Compression::Decompress(Some(codec)), &compressed), data);
+            assert_eq!(run(Compression::Decompress(None), &compressed), data);
+            // concatenated streams, like `cat a.gz b.gz` or pigz output
+            let twice = [compressed.clone(), compressed].concat();
+            assert_eq!(
+                run(Compression::Decompress(Some(codec)), &twice),
+                [data.clone(), data.clone()].concat()
+            );
+        }
+    }
+
+    #[test]
+    fn auto_rejects_unknown_format() {
+        let mut compressor = Compressor::new(Compression::Decompress(None));
+        assert!(compressor.update(b"plain text", &mut Vec::new()).is_err());
+        assert!(run(Compression::Decompress(None), b"").is_empty());
+    }
+}
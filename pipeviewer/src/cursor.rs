@@ -0,0 +1,239 @@
+//! Terminal rows shared by several pipeviewers writing to the same terminal, like `pv -c`.
+//!
+//! The instances register in a state file named after the terminal, under
+//! `$XDG_RUNTIME_DIR` or else the temp directory, and the last one out removes it.
+//! Each one reserves the row below the others and draws it with relative cursor
+//! moves, always returning to the line below the block. Every step holds an
+//! exclusive `flock` on the state file, since the cursor is shared by all of them.
+
+use crossterm::{cursor, execute};
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Stderr, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Rows in use and the instances still drawing them
+#[derive(Debug, Default, PartialEq)]
+struct State {
+    rows: usize,
+    pids: Vec<u32>,
+}
+
+impl State {
+    fn parse(text: &str) -> Self {
+        let mut lines = text.lines();
+        let rows = lines.next().and_then(|line| line.parse().ok()).unwrap_or(0);
+        let pids = lines.filter_map(|line| line.parse().ok()).collect();
+        Self { rows, pids }
+    }
+
+    fn format(&self) -> String {
+        let mut text = format!("{}\n", self.rows);
+        for pid in &self.pids {
+            text.push_str(&format!("{}\n", pid));
+        }
+        text
+    }
+}
+
+/// An exclusive lock on the state file, released on drop
+struct Locked<'a> {
+    file: &'a File,
+}
+
+impl<'a> Locked<'a> {
+    fn new(file: &'a File) -> io::Result<Self> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { file })
+    }
+
+    fn read(&self) -> io::Result<State> {
+        let mut text = String::new();
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut text)?;
+        Ok(State::parse(&text))
+    }
+
+    fn write(&self, state: &State) -> io::Result<()> {
+        let mut file = self.file;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(state.format().as_bytes())
+    }
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+fn alive(pid: u32) -> bool {
+    pid == std::process::id() || unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+}
+
+/// State file for the terminal behind stderr, `None` when stderr isn't a terminal
+fn state_path() -> Option<PathBuf> {
+    let name = unsafe { libc::ttyname(libc::STDERR_FILENO) };
+    if name.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .replace('/', "-");
+    let uid = unsafe { libc::getuid() };
+    // private to the user, unlike the shared temp directory
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(env::temp_dir);
+    Some(dir.join(format!("pipeviewer-cursor-{}{}", uid, name)))
+}
+
+/// Opens the state file without following a symlink planted in its place
+fn open_state(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        // the other instances' rows are in there
+        .truncate(false)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+/// Whether `file` is still the one at `path`, the last instance out removes it
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    let (open, named) = match (file.metadata(), fs::symlink_metadata(path)) {
+        (Ok(open), Ok(named)) => (open, named),
+        (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+    Ok(open.dev() == named.dev() && open.ino() == named.ino())
+}
+
+/// This instance's row in the block of progress lines
+pub struct Row {
+    file: File,
+    path: PathBuf,
+    index: usize,
+}
+
+impl Row {
+    /// Reserves a row below the ones of the other running instances.
+    /// Returns `None` when stderr isn't a terminal.
+    pub fn register(stderr: &mut Stderr) -> io::Result<Option<Self>> {
+        let path = match state_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        loop {
+            let file = open_state(&path)?;
+            let index = {
+                let lock = Locked::new(&file)?;
+                // removed by the last instance while we were waiting for the lock
+                if !is_current(&file, &path)? {
+                    continue;
+                }
+                let mut state = lock.read()?;
+                state.pids.retain(|&pid| alive(pid));
+                if state.pids.is_empty() {
+                    // the rows of earlier runs are history, start below them
+                    state.rows = 0;
+                }
+                let index = state.rows;
+                state.rows += 1;
+                state.pids.push(std::process::id());
+                lock.write(&state)?;
+                // the cursor is on the new row, move it below the block
+                writeln!(stderr)?;
+                index
+            };
+            return Ok(Some(Self { file, path, index }));
+        }
+    }
+
+    /// Runs `draw` on this row and puts the cursor back below the block
+    pub fn draw(&self, stderr: &mut Stderr, draw: impl FnOnce(&mut Stderr)) {
+        let lock = match Locked::new(&self.file) {
+            Ok(lock) => lock,
+            Err(_) => return,
+        };
+        let rows = lock.read().map(|state| state.rows).unwrap_or(0);
+        let up = rows.saturating_sub(self.index) as u16;
+        if up == 0 {
+            // the state was reset underneath us, there is nowhere to draw
+            return;
+        }
+        let _ = execute!(stderr, cursor::MoveUp(up));
+        draw(stderr);
+        let _ = execute!(stderr, cursor::MoveDown(up), cursor::MoveToColumn(0));
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for Row {
+    fn drop(&mut self) {
+        if let Ok(lock) = Locked::new(&self.file) {
+            if let Ok(mut state) = lock.read() {
+                let pid = std::process::id();
+                state.pids.retain(|&other| other != pid && alive(other));
+                if state.pids.is_empty() {
+                    let _ = fs::remove_file(&self.path);
+                } else {
+                    let _ = lock.write(&state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_current, open_state, State};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn state_round_trips() {
+        let state = State {
+            rows: 3,
+            pids: vec![12, 345],
+        };
+        assert_eq!(state.format(), "3\n12\n345\n");
+        assert_eq!(State::parse(&state.format()), state);
+        assert_eq!(State::parse(""), State::default());
+    }
+
+    #[test]
+    fn state_file_is_not_followed() {
+        let dir = std::env::temp_dir();
+        let target = dir.join(format!(
+            "pipeviewer-cursor-test-{}-target",
+            std::process::id()
+        ));
+        let link = dir.join(format!(
+            "pipeviewer-cursor-test-{}-link",
+            std::process::id()
+        ));
+        fs::write(&target, "precious").unwrap();
+        let _ = fs::remove_file(&link);
+        symlink(&target, &link).unwrap();
+        let opened = open_state(&link);
+        let _ = fs::remove_file(&link);
+
+        assert!(opened.is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "precious");
+        let file = open_state(&target).unwrap();
+        assert!(is_current(&file, &target).unwrap());
+        fs::remove_file(&target).unwrap();
+        assert!(!is_current(&file, &target).unwrap());
+    }
+}
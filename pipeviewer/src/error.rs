@@ -0,0 +1,151 @@
+//! Errors reported by the pipeline stages.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    /// Opening or reading an input failed
+    Read { name: String, source: io::Error },
+    /// Creating or writing an output failed
+    Write { name: String, source: io::Error },
+    /// Copying between two descriptors in the kernel failed
+    Copy {
+        from: String,
+        to: String,
+        source: io::Error,
+    },
+    /// A transform stage such as compression failed on the stream
+    Transform { name: String, source: io::Error },
+    /// The named upstream stage went away without sending its end of stream message
+    Disconnected(&'static str),
+    /// Listening for or sending remote commands failed
+    Control { name: String, source: io::Error },
+    /// Listening on a network address failed
+    Listen { addr: String, source: io::Error },
+    /// The thread running the named stage panicked
+    Panicked(&'static str),
+    /// No data came through for the given stall timeout
+    Stalled(Duration),
+    /// The reader of the named output went away, what SIGPIPE would report
+    BrokenPipe(String),
+    /// Stopped early on the given signal
+    Interrupted(i32),
+}
+
+pub type Result<T> = std::result::Result<T, PipelineError>;
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Read { name, source } => write!(f, "reading {}: {}", name, source),
+            PipelineError::Write { name, source } => write!(f, "writing {}: {}", name, source),
+            PipelineError::Copy { from, to, source } => {
+                write!(f, "copying {} to {}: {}", from, to, source)
+            }
+            PipelineError::Transform { name, source } => write!(f, "{}: {}", name, source),
+            PipelineError::Control { name, source } => {
+                write!(f, "remote control of {}: {}", name, source)
+            }
+            PipelineError::Disconnected(stage) => {
+                write!(f, "{} stage disconnected unexpectedly", stage)
+            }
+            PipelineError::Listen { addr, source } => {
+                write!(f, "listening on {}: {}", addr, source)
+            }
+            PipelineError::Panicked(stage) => write!(f, "{} thread panicked", stage),
+            PipelineError::Stalled(timeout) => {
+                write!(f, "no data for {}s, giving up", timeout.as_secs_f64())
+            }
+            PipelineError::BrokenPipe(name) => write!(f, "writing {}: broken pipe", name),
+            PipelineError::Interrupted(signal) => write!(f, "interrupted by signal {}", signal),
+        }
+    }
+}
+
+impl PipelineError {
+    /// Process exit status for this error, so scripts can tell a stall from a failure
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // same as timeout(1)
+            PipelineError::Stalled(_) => 124,
+            // what the shell reports for a process killed by the signal
+            PipelineError::BrokenPipe(_) => 128 + 13,
+            PipelineError::Interrupted(signal) => 128 + signal,
+            _ => 1,
+        }
+    }
+
+    /// Whether this stands for a signal, which the shell reports without a message
+    pub fn is_signal(&self) -> bool {
+        matches!(
+            self,
+            PipelineError::BrokenPipe(_) | PipelineError::Interrupted(_)
+        )
+    }
+}
+
+/// A failure the pipeline keeps going after
+#[derive(Debug)]
+pub enum Warning {
+    /// An output failed and was dropped, the others still get the stream
+    OutputDropped(PipelineError),
+    /// The remote control socket couldn't be set up, the copy runs without it
+    NoRemoteControl(PipelineError),
+}
+
+/// Receives the warnings of a pipeline, on whichever stage thread they happen
+pub type WarningHandler = Arc<dyn Fn(&Warning) + Send + Sync>;
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::OutputDropped(e) => write!(f, "{}, dropping output", e),
+            Warning::NoRemoteControl(e) => write!(f, "{}, running without remote control", e),
+        }
+    }
+}
+
+impl Error for PipelineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PipelineError::Read { source, .. }
+            | PipelineError::Write { source, .. }
+            | PipelineError::Copy { source, .. }
+            | PipelineError::Transform { source, .. }
+            | PipelineError::Control { source, .. }
+            | PipelineError::Listen { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PipelineError;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn exit_codes() {
+        let read = PipelineError::Read {
+            name: String::from("in"),
+            source: io::Error::other("bad"),
+        };
+        let cases = [
+            (read, 1, false),
+            (PipelineError::Panicked("read"), 1, false),
+            (PipelineError::Stalled(Duration::from_secs(5)), 124, false),
+            (PipelineError::BrokenPipe(String::from("stdout")), 141, true),
+            (PipelineError::Interrupted(2), 130, true),
+            (PipelineError::Interrupted(15), 143, true),
+        ];
+        for (error, code, signal) in &cases {
+            assert_eq!(error.exit_code(), *code, "{}", error);
+            assert_eq!(error.is_signal(), *signal, "{}", error);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! # Pipeviewer library documentation
+//! This is library for pipeviewer project.
+//! The [`Pipeline`] builder copies any `Read` inputs to `Write` outputs
+//! while reporting the progress to stats observers.
+
+pub mod checksum;
+pub mod compress;
+#[cfg(unix)]
+pub mod cursor;
+pub mod error;
+pub mod listener;
+pub mod metrics;
+pub mod observer;
+pub mod pipeline;
+pub mod pool;
+pub mod ratelimit;
+pub mod read;
+pub mod remote;
+pub mod signal;
+#[cfg(target_os = "linux")]
+pub mod splice;
+pub mod stats;
+pub mod template;
+pub mod transform;
+pub mod units;
+pub mod watch;
+pub mod write;
+
+pub use pipeline::Pipeline;
+
+/// Default size of the buffers read at once
+pub const DEFAULT_BUFFER_SIZE: usize = 16 * 1024;
+/// Default number of chunks that may queue up in front of each stage
+pub const DEFAULT_QUEUE_DEPTH: usize = 1024;
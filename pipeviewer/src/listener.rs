@@ -0,0 +1,56 @@
+//! Background thread answering connections, shared by the metrics and remote
+//! control servers.
+
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the thread checks whether it should stop while nobody connects
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Hands every connection to a callback until stopped or dropped
+pub struct Acceptor {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Acceptor {
+    /// Calls `serve` with each connection `accept` returns. `accept` has to be
+    /// non-blocking, returning `WouldBlock` while nobody is connecting.
+    pub fn spawn<S>(
+        mut accept: impl FnMut() -> io::Result<S> + Send + 'static,
+        mut serve: impl FnMut(S) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match accept() {
+                    Ok(connection) => serve(connection),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => break,
+                }
+            }
+        });
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stops taking connections and waits for the thread
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Acceptor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -1,36 +1,183 @@
-mod mylib;
-use crossbeam::channel::{bounded, unbounded};
-use mylib::{args::Args, read, stats, write};
-use std::io::Result as IoResult;
-use std::thread;
+mod args;
+use args::{Args, Format};
+use pipeviewer::compress::{Compression, Compressor};
+use pipeviewer::error::{PipelineError, Result};
+use pipeviewer::metrics::Metrics;
+use pipeviewer::observer::{JsonLines, Numeric, Observer, Terminal};
+use pipeviewer::read::Input;
+use pipeviewer::remote;
+use pipeviewer::signal;
+use pipeviewer::stats::Stats;
+use pipeviewer::watch;
+use pipeviewer::write::Output;
+use pipeviewer::Pipeline;
+use std::io;
+use std::process;
+use std::sync::{Arc, Mutex};
 
-fn main() -> IoResult<()> {
+fn main() {
+    signal::install();
+    if let Err(e) = run() {
+        if !e.is_signal() {
+            eprintln!("pipeviewer: {}", e);
+        }
+        process::exit(e.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
     let args = Args::new();
     let Args {
-        infile,
-        outfile,
+        infiles,
+        outfiles,
+        drop_failed,
         silent,
+        format,
+        rate_limit,
+        size,
+        line_mode,
+        skip,
+        stop_at_size,
+        skip_errors,
+        checksum,
+        checksum_file,
+        buffer_size,
+        queue_depth,
+        no_splice,
+        compression,
+        transforms,
+        watchfd,
+        cursor,
+        name,
+        units,
+        stall_timeout,
+        metrics_addr,
+        listen,
+        connect,
+        remote,
+        commands,
     } = args;
 
-    let (stats_tx, stats_rx) = unbounded();
-    let (write_tx, write_rx) = bounded(1024);
-
-    // let quit = Arc::new(Mutex::new(false));
-    // let (quit1, quit2, quit3) = (quit.clone(), quit.clone(), quit.clone());
-
-    let read_handle = thread::spawn(move || read::read_loop(&infile, stats_tx, write_tx));
-    let stats_handle = thread::spawn(move || stats::stats_loop(silent, stats_rx));
-    let write_handle = thread::spawn(move || write::write_loop(&outfile, write_rx));
+    if let Some(pid) = remote {
+        return remote::send(pid, &commands);
+    }
 
-    //crash if any threads have crashed
-    // `.join()` returns a `thread::Result<io::Result<()>>`
-    let read_io_result = read_handle.join().unwrap();
-    let stat_io_result = stats_handle.join().unwrap();
-    let write_io_result = write_handle.join().unwrap();
+    if let Some((pid, fd)) = watchfd {
+        let mut observers: Vec<Box<dyn Observer>> = Vec::new();
+        if !silent {
+            observers.push(match format {
+                Format::Terminal(template) => {
+                    Box::new(Terminal::multi_line().with_template(template))
+                }
+                Format::Json => Box::new(JsonLines::new(io::stderr())),
+                Format::Numeric => Box::new(Numeric::new(io::stderr())),
+            });
+        }
+        return watch::watch_loop(pid, fd, units, observers);
+    }
 
-    read_io_result?;
-    stat_io_result?;
-    write_io_result?;
+    let mut pipeline = Pipeline::new()
+        .line_mode(line_mode)
+        .drop_failed(drop_failed)
+        .on_warning(|warning| eprintln!("pipeviewer: {}", warning))
+        .skip_errors(skip_errors)
+        .splice(!no_splice)
+        .units(units);
+    #[cfg(unix)]
+    {
+        pipeline = pipeline.remote(remote::socket_path(process::id()));
+    }
+    if let Some(addr) = listen {
+        pipeline = pipeline.input(Input::listen(&addr)?);
+    } else if infiles.is_empty() {
+        pipeline = pipeline.input(Input::stdin());
+    }
+    for infile in &infiles {
+        pipeline = pipeline.input(Input::open(infile)?);
+    }
+    if let Some(addr) = connect {
+        pipeline = pipeline.output(Output::connect(&addr)?);
+    } else if outfiles.is_empty() {
+        pipeline = pipeline.output(Output::stdout());
+    }
+    for outfile in &outfiles {
+        pipeline = pipeline.try_output(Output::create(outfile));
+    }
+    if let Some(name) = name {
+        pipeline = pipeline.label(name);
+    }
+    if let Some(rate) = rate_limit {
+        pipeline = pipeline.rate_limit(rate);
+    }
+    if let Some(size) = size {
+        pipeline = pipeline.size(size);
+        if stop_at_size {
+            pipeline = pipeline.stop_at(size);
+        }
+    }
+    if let Some(skip) = skip {
+        pipeline = pipeline.skip(skip);
+    }
+    if let Some(timeout) = stall_timeout {
+        pipeline = pipeline.stall_timeout(timeout);
+    }
+    if let Some(buffer_size) = buffer_size {
+        pipeline = pipeline.buffer_size(buffer_size);
+    }
+    if let Some(queue_depth) = queue_depth {
+        pipeline = pipeline.queue_depth(queue_depth);
+    }
+    // decompress before and compress after the filters
+    if let Some(decompress @ Compression::Decompress(_)) = compression {
+        pipeline = pipeline.transform(Compressor::new(decompress));
+    }
+    for transform in transforms {
+        pipeline = pipeline.transform(transform);
+    }
+    if let Some(compress @ Compression::Compress(_)) = compression {
+        pipeline = pipeline.transform(Compressor::new(compress));
+    }
+    // printed once the display is done, the JSON lines carry it already
+    let digest = Arc::new(Mutex::new(None));
+    if let Some(algorithm) = checksum {
+        pipeline = pipeline.checksum(algorithm);
+        match checksum_file {
+            Some(path) => pipeline = pipeline.checksum_file(path),
+            None if !silent && matches!(format, Format::Json) => (),
+            None => {
+                let digest = digest.clone();
+                pipeline = pipeline.observer(move |stats: &Stats| {
+                    if let Some(checksum) = stats.checksum() {
+                        *digest.lock().unwrap() =
+                            Some(format!("{}: {}", algorithm.name(), checksum));
+                    }
+                })
+            }
+        }
+    }
+    if let Some(addr) = metrics_addr {
+        let metrics = Metrics::serve(&addr).map_err(|source| PipelineError::Listen {
+            addr: addr.clone(),
+            source,
+        })?;
+        pipeline = pipeline.observer(metrics);
+    }
+    if !silent {
+        pipeline = match format {
+            Format::Terminal(template) if cursor => {
+                pipeline.observer(Terminal::cursor().with_template(template))
+            }
+            Format::Terminal(template) => {
+                pipeline.observer(Terminal::new().with_template(template))
+            }
+            Format::Json => pipeline.observer(JsonLines::new(io::stderr())),
+            Format::Numeric => pipeline.observer(Numeric::new(io::stderr())),
+        };
+    }
 
+    pipeline.run()?;
+    if let Some(line) = digest.lock().unwrap().take() {
+        eprintln!("{}", line);
+    }
     Ok(())
 }
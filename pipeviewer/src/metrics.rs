@@ -0,0 +1,250 @@
+//! Progress in the Prometheus text format, served over HTTP for scraping.
+//!
+//! [`Metrics`] is an observer that renders every update into a snapshot, which a
+//! listener thread hands to whoever asks for `GET /metrics`.
+
+use super::listener::Acceptor;
+use super::observer::Observer;
+use super::stats::Stats;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Largest request head read from a client
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// Serves the latest stats until dropped
+pub struct Metrics {
+    snapshot: Arc<Mutex<String>>,
+    addr: SocketAddr,
+    stalled: bool,
+    stalls: u64,
+    /// Answers the scrapes until the metrics are dropped
+    _acceptor: Acceptor,
+}
+
+impl Metrics {
+    /// Listens on `addr`, e.g. `127.0.0.1:9100`
+    pub fn serve(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let snapshot = Arc::new(Mutex::new(String::new()));
+        let served = snapshot.clone();
+        let acceptor = Acceptor::spawn(
+            move || listener.accept().map(|(stream, _)| stream),
+            move |stream| {
+                let _ = respond(stream, &served);
+            },
+        );
+        Ok(Self {
+            snapshot,
+            addr,
+            stalled: false,
+            stalls: 0,
+            _acceptor: acceptor,
+        })
+    }
+
+    /// The address actually listened on, useful with port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Observer for Metrics {
+    fn update(&mut self, stats: &Stats) {
+        if stats.is_stalled() && !self.stalled {
+            self.stalls += 1;
+        }
+        self.stalled = stats.is_stalled();
+        let text = render(stats, self.stalls);
+        if let Ok(mut snapshot) = self.snapshot.lock() {
+            *snapshot = text;
+        }
+    }
+}
+
+/// Answers one HTTP request, only `/metrics` exists
+fn respond(mut stream: TcpStream, snapshot: &Mutex<String>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            num => request.extend_from_slice(&buffer[..num]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            snapshot.lock().map(|text| text.clone()).unwrap_or_default(),
+        ),
+        _ => ("404 Not Found", String::from("try /metrics\n")),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Quotes a label value, escaping what the text format requires
+fn label_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Renders the stats as gauges and counters, labelled with the `--name` if any
+fn render(stats: &Stats, stalls: u64) -> String {
+    let unit = if stats.is_line_mode() {
+        "lines"
+    } else {
+        "bytes"
+    };
+    let labels = match stats.label() {
+        Some(label) => format!("{{name={}}}", label_value(label)),
+        None => String::new(),
+    };
+    let mut text = String::new();
+    let mut metric = |name: String, kind: &str, help: &str, value: f64| {
+        let _ = writeln!(text, "# HELP pipeviewer_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE pipeviewer_{} {}", name, kind);
+        let _ = writeln!(text, "pipeviewer_{}{} {}", name, labels, value);
+    };
+    metric(
+        format!("{}_total", unit),
+        "counter",
+        "Amount read so far",
+        stats.total() as f64,
+    );
+    if let Some(output) = stats.output_total() {
+        metric(
+            String::from("output_bytes_total"),
+            "counter",
+            "Bytes written after the transforms",
+            output as f64,
+        );
+    }
+    metric(
+        String::from("skipped_bytes_total"),
+        "counter",
+        "Unreadable bytes written as zeros",
+        stats.skipped() as f64,
+    );
+    if let Some(size) = stats.size() {
+        metric(
+            format!("size_{}", unit),
+            "gauge",
+            "Expected total amount",
+            size as f64,
+        );
+    }
+    let rates = [
+        ("rate", "Rate over the last display period", stats.rate()),
+        (
+            "smoothed_rate",
+            "Exponentially smoothed rate",
+            stats.smoothed_rate(),
+        ),
+        ("average_rate", "Rate since the start", stats.average_rate()),
+        (
+            "peak_rate",
+            "Highest rate over a display period",
+            stats.peak_rate(),
+        ),
+        (
+            "window_rate",
+            "Rate over the last ten seconds",
+            stats.window_rate(),
+        ),
+    ];
+    for &(name, help, rate) in &rates {
+        metric(format!("{}_{}_per_second", name, unit), "gauge", help, rate);
+    }
+    metric(
+        String::from("elapsed_seconds"),
+        "gauge",
+        "Time since the start",
+        stats.elapsed().as_secs_f64(),
+    );
+    if let Some(eta) = stats.eta() {
+        metric(
+            String::from("eta_seconds"),
+            "gauge",
+            "Estimated time left",
+            eta as f64,
+        );
+    }
+    let flag = |set: bool| if set { 1.0 } else { 0.0 };
+    metric(
+        String::from("stalled"),
+        "gauge",
+        "Whether nothing has flowed for a while",
+        flag(stats.is_stalled()),
+    );
+    metric(
+        String::from("stalls_total"),
+        "counter",
+        "Number of times the transfer stalled",
+        stalls as f64,
+    );
+    metric(
+        String::from("paused"),
+        "gauge",
+        "Whether the transfer was paused remotely",
+        flag(stats.is_paused()),
+    );
+    metric(
+        String::from("finished"),
+        "gauge",
+        "Whether the stream has ended",
+        flag(stats.is_finished()),
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{label_value, Metrics};
+    use crate::observer::Observer;
+    use crate::stats::Stats;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(label_value("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+    }
+
+    #[test]
+    fn serves_the_latest_update() {
+        let mut metrics = Metrics::serve("127.0.0.1:0").unwrap();
+        let mut stats = Stats::new(None, Some(200), false).with_label("job");
+        stats.update(50);
+        metrics.update(&stats);
+
+        let mut stream = TcpStream::connect(metrics.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE pipeviewer_bytes_total counter\n"));
+        assert!(response.contains("\npipeviewer_bytes_total{name=\"job\"} 50\n"));
+        assert!(response.contains("\npipeviewer_size_bytes{name=\"job\"} 200\n"));
+        assert!(response.contains("\npipeviewer_stalled{name=\"job\"} 0\n"));
+    }
+}
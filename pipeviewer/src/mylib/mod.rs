@@ -1,9 +0,0 @@
-//! # Pipeviewer library documentation
-//! This is library for pipeviewer project
-
-pub mod args;
-pub mod read;
-pub mod stats;
-pub mod write;
-
-const CHUNK_SIZE: usize = 16 * 1024;
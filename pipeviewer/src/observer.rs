@@ -0,0 +1,293 @@
+//! Observers receive the stats on the stats thread and present them somewhere.
+
+#[cfg(unix)]
+use super::cursor::Row;
+use super::stats::Stats;
+use super::template::Template;
+use crossterm::{cursor, execute};
+use std::io::{self, Stderr, Write};
+
+pub trait Observer: Send {
+    /// Called once per display period while data is flowing
+    fn update(&mut self, stats: &Stats);
+
+    /// Called once after the stream has ended
+    fn finish(&mut self, stats: &Stats) {
+        self.update(stats);
+    }
+
+    /// Switches to another terminal line layout, ignored by the other observers
+    fn set_template(&mut self, _template: &Template) {}
+}
+
+/// Closures are observers that see every call, `Stats::is_finished` tells the last one apart
+impl<F: FnMut(&Stats) + Send> Observer for F {
+    fn update(&mut self, stats: &Stats) {
+        self(stats)
+    }
+}
+
+/// The default single line display, rewritten in place on stderr
+pub struct Terminal {
+    stderr: Stderr,
+    template: Template,
+    /// Labels of the lines drawn so far in multi-line mode
+    rows: Option<Vec<String>>,
+    /// Line the cursor is on, `rows.len()` being the one below the block
+    cursor_row: usize,
+    /// Row shared with other instances on the same terminal in cursor mode
+    #[cfg(unix)]
+    row: Option<Row>,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        Self {
+            stderr: io::stderr(),
+            template: Template::default(),
+            rows: None,
+            cursor_row: 0,
+            #[cfg(unix)]
+            row: None,
+        }
+    }
+
+    /// Draws on a row of its own, so that several pipeviewers in one pipeline
+    /// don't overwrite each other. Falls back to the single line when stderr
+    /// isn't a terminal.
+    pub fn cursor() -> Self {
+        #[cfg(unix)]
+        {
+            let mut terminal = Self::new();
+            terminal.row = Row::register(&mut terminal.stderr).ok().flatten();
+            terminal
+        }
+        #[cfg(not(unix))]
+        Self::new()
+    }
+
+    /// Lays the line out by `template` instead of the default layout
+    pub fn with_template(mut self, template: Template) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Draws one line per stats label (or input name) instead of a single line,
+    /// for watching several files at once
+    pub fn multi_line() -> Self {
+        Self {
+            rows: Some(Vec::new()),
+            ..Self::new()
+        }
+    }
+
+    /// Moves the cursor to the line for `stats`, starting a new line for a new label
+    fn seek_row(&mut self, stats: &Stats) {
+        let rows = match self.rows.as_mut() {
+            Some(rows) => rows,
+            None => return,
+        };
+        let key = stats.label().or_else(|| stats.input_name()).unwrap_or("");
+        let (row, new) = match rows.iter().position(|label| label == key) {
+            Some(row) => (row, false),
+            None => {
+                rows.push(key.to_string());
+                (rows.len() - 1, true)
+            }
+        };
+        if row > self.cursor_row {
+            // a new line has to be started, moving down stops at the bottom of the screen
+            let down = row - self.cursor_row - new as usize;
+            if down > 0 {
+                let _ = execute!(self.stderr, cursor::MoveDown(down as u16));
+            }
+            if new {
+                let _ = writeln!(self.stderr);
+            }
+        } else if row < self.cursor_row {
+            let up = (self.cursor_row - row) as u16;
+            let _ = execute!(self.stderr, cursor::MoveUp(up));
+        }
+        self.cursor_row = row;
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Terminal {
+    fn update(&mut self, stats: &Stats) {
+        #[cfg(unix)]
+        if let Some(row) = &self.row {
+            let template = &self.template;
+            row.draw(&mut self.stderr, |stderr| stats.print(stderr, template));
+            return;
+        }
+        self.seek_row(stats);
+        stats.print(&mut self.stderr, &self.template);
+    }
+
+    fn set_template(&mut self, template: &Template) {
+        self.template = template.clone();
+    }
+
+    fn finish(&mut self, stats: &Stats) {
+        self.update(stats);
+        #[cfg(unix)]
+        if self.row.is_some() {
+            // the cursor is below the rows already
+            return;
+        }
+        // leaves the cursor below the other lines as well
+        if let Some(count) = self.rows.as_ref().map(Vec::len) {
+            let below = (count - 1 - self.cursor_row) as u16;
+            if below > 0 {
+                let _ = execute!(self.stderr, cursor::MoveDown(below));
+            }
+            self.cursor_row = count;
+        }
+        let _ = writeln!(self.stderr);
+        // a summary would break up the block of lines in multi-line mode
+        if self.rows.is_none() {
+            let _ = writeln!(self.stderr, "{}", stats.summary());
+        }
+    }
+}
+
+/// Machine readable output, one JSON object per line
+pub struct JsonLines<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> Observer for JsonLines<W> {
+    fn update(&mut self, stats: &Stats) {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("null"));
+        let _ = writeln!(
+            self.writer,
+            "{{\"elapsed\":{:.3},\"unit\":\"{}\",\"total\":{},\"rate\":{:.0},\"smoothed_rate\":{:.0},\"average_rate\":{:.0},\"peak_rate\":{:.0},\"window_rate\":{:.0},\"human_total\":{},\"human_rate\":{},\"size\":{},\"percentage\":{},\"eta\":{},\"label\":{},\"input\":{},\"checksum\":{},\"output\":{},\"ratio\":{},\"skipped\":{},\"paused\":{},\"stalled\":{},\"finished\":{}}}",
+            stats.elapsed().as_secs_f64(),
+            if stats.is_line_mode() { "lines" } else { "bytes" },
+            stats.total(),
+            stats.rate(),
+            stats.smoothed_rate(),
+            stats.average_rate(),
+            stats.peak_rate(),
+            stats.window_rate(),
+            json_string(&stats.human_total()),
+            json_string(&stats.human_rate()),
+            optional(stats.size().map(|size| size.to_string())),
+            optional(stats.percentage().map(|p| format!("{:.1}", p))),
+            optional(stats.eta().map(|eta| eta.to_string())),
+            optional(stats.label().map(json_string)),
+            optional(stats.input_name().map(json_string)),
+            optional(stats.checksum().map(json_string)),
+            optional(stats.output_total().map(|output| output.to_string())),
+            optional(stats.ratio().map(|ratio| format!("{:.3}", ratio))),
+            stats.skipped(),
+            stats.is_paused(),
+            stats.is_stalled(),
+            stats.is_finished()
+        );
+        let _ = self.writer.flush();
+    }
+}
+
+/// Quotes and escapes `value` as a JSON string
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Integer percentages on separate lines like `pv -n`, suitable for `dialog --gauge`.
+/// Without a known size the running total is printed instead.
+pub struct Numeric<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> Numeric<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> Observer for Numeric<W> {
+    fn update(&mut self, stats: &Stats) {
+        let _ = match stats.percentage() {
+            Some(percentage) => writeln!(self.writer, "{:.0}", percentage.floor()),
+            None => writeln!(self.writer, "{}", stats.total()),
+        };
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_string, JsonLines, Numeric, Observer};
+    use crate::stats::Stats;
+
+    #[test]
+    fn json_string_escaping() {
+        assert_eq!(json_string("a \"b\"\\c\n"), "\"a \\\"b\\\"\\\\c\\n\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn json_lines_record() {
+        let mut stats = Stats::new(None, Some(200), false);
+        stats.start_input(String::from("in\"put"), None, 0, 1);
+        stats.update(50);
+        let mut json = JsonLines::new(Vec::new());
+        json.update(&stats);
+        let line = String::from_utf8(json.into_inner()).unwrap();
+        assert!(line.ends_with("}\n"));
+        assert!(line.contains("\"unit\":\"bytes\",\"total\":50,"));
+        assert!(line.contains("\"human_total\":\"50 B\","));
+        assert!(line.contains("\"size\":200,\"percentage\":25.0,"));
+        assert!(line.contains("\"input\":\"in\\\"put\",\"checksum\":null,\"output\":null,\"ratio\":null,\"skipped\":0,\"paused\":false,\"stalled\":false,\"finished\":false"));
+    }
+
+    #[test]
+    fn numeric_percentage_or_total() {
+        let mut stats = Stats::new(None, Some(3), false);
+        stats.update(1);
+        let mut numeric = Numeric::new(Vec::new());
+        numeric.update(&stats);
+        assert_eq!(numeric.into_inner(), b"33\n");
+
+        let mut stats = Stats::new(None, None, true);
+        stats.update(7);
+        let mut numeric = Numeric::new(Vec::new());
+        numeric.update(&stats);
+        assert_eq!(numeric.into_inner(), b"7\n");
+    }
+}
@@ -0,0 +1,684 @@
+//! The pipeline builder wiring inputs, outputs and observers to the stage threads.
+
+use super::checksum::Algorithm;
+use super::error::{PipelineError, Result, Warning, WarningHandler};
+use super::observer::Observer;
+use super::pool::BufferPool;
+use super::ratelimit::Throttle;
+use super::read::{self, Input, Range};
+#[cfg(unix)]
+use super::remote;
+use super::signal;
+#[cfg(target_os = "linux")]
+use super::splice;
+use super::stats::{self, Stats};
+use super::transform::{self, Transform};
+use super::units::Units;
+use super::write::{self, Dropped, Output};
+use super::{DEFAULT_BUFFER_SIZE, DEFAULT_QUEUE_DEPTH};
+use crossbeam::channel::{bounded, unbounded};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Copies inputs to outputs on separate read, stats and write threads.
+///
+/// # Example
+/// ```rust
+/// use pipeviewer::{read::Input, stats::Stats, write::Output, Pipeline};
+/// use std::io::{self, Cursor};
+///
+/// Pipeline::new()
+///     .input(Input::new("greeting", Cursor::new(b"hello world".to_vec())))
+///     .output(Output::new("nowhere", io::sink()))
+///     .observer(|stats: &Stats| eprintln!("{}", stats))
+///     .run()
+///     .unwrap();
+/// ```
+pub struct Pipeline {
+    inputs: Vec<Input>,
+    outputs: Vec<Output>,
+    /// Outputs that failed to open, handled like outputs failing later
+    failed_outputs: Vec<PipelineError>,
+    observers: Vec<Box<dyn Observer>>,
+    rate_limit: Option<usize>,
+    size: Option<usize>,
+    line_mode: bool,
+    drop_failed: bool,
+    checksum: Option<Algorithm>,
+    checksum_file: Option<PathBuf>,
+    buffer_size: usize,
+    queue_depth: usize,
+    splice: bool,
+    transforms: Vec<Box<dyn Transform>>,
+    label: Option<String>,
+    units: Units,
+    stall_timeout: Option<Duration>,
+    #[cfg(unix)]
+    remote: Option<PathBuf>,
+    range: Range,
+    skip_errors: bool,
+    warn: WarningHandler,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            failed_outputs: Vec::new(),
+            observers: Vec::new(),
+            rate_limit: None,
+            size: None,
+            line_mode: false,
+            drop_failed: false,
+            checksum: None,
+            checksum_file: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            splice: true,
+            transforms: Vec::new(),
+            label: None,
+            units: Units::default(),
+            stall_timeout: None,
+            #[cfg(unix)]
+            remote: None,
+            range: Range::default(),
+            skip_errors: false,
+            warn: Arc::new(|_: &Warning| ()),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an input, inputs are read one after another
+    pub fn input(mut self, input: Input) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Adds an output, every output receives the whole stream
+    pub fn output(mut self, output: Output) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Adds an output that may have failed to open, e.g. `Output::create(path)`.
+    /// A failure ends the run, or with `drop_failed` only drops this output.
+    pub fn try_output(mut self, output: Result<Output>) -> Self {
+        match output {
+            Ok(output) => self.outputs.push(output),
+            Err(e) => self.failed_outputs.push(e),
+        }
+        self
+    }
+
+    /// Adds an observer (or a `FnMut(&Stats)` closure) run on the stats thread
+    /// once per second and once at the end
+    pub fn observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Limits the transfer to `rate` bytes per second, 0 lifting the limit
+    pub fn rate_limit(mut self, rate: usize) -> Self {
+        self.rate_limit = Some(rate).filter(|&rate| rate > 0);
+        self
+    }
+
+    /// Overrides the expected size, which defaults to the sum of the input sizes
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Drops the first `bytes` of the input, seeking over them in regular files
+    pub fn skip(mut self, bytes: usize) -> Self {
+        self.range.skip = bytes;
+        self
+    }
+
+    /// Stops after copying `bytes`, which also makes the default size
+    pub fn stop_at(mut self, bytes: usize) -> Self {
+        self.range.limit = Some(bytes);
+        self
+    }
+
+    /// Keeps going past read errors in regular files, writing zeros for the
+    /// unreadable blocks. Turns off the kernel copy, which can't do that.
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    /// Calls `warn` for the failures the pipeline keeps going after, such as an
+    /// output dropped with `drop_failed`. They are ignored by default.
+    pub fn on_warning(mut self, warn: impl Fn(&Warning) + Send + Sync + 'static) -> Self {
+        self.warn = Arc::new(warn);
+        self
+    }
+
+    /// Counts lines instead of bytes
+    pub fn line_mode(mut self, line_mode: bool) -> Self {
+        self.line_mode = line_mode;
+        self
+    }
+
+    /// Keeps writing to the remaining outputs when one of them fails
+    pub fn drop_failed(mut self, drop_failed: bool) -> Self {
+        self.drop_failed = drop_failed;
+        self
+    }
+
+    /// Computes a checksum of the whole stream, see `Stats::checksum`
+    pub fn checksum(mut self, algorithm: Algorithm) -> Self {
+        self.checksum = Some(algorithm);
+        self
+    }
+
+    /// Also writes the checksum to `path` in the `sha256sum` format
+    pub fn checksum_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checksum_file = Some(path.into());
+        self
+    }
+
+    /// Reads at most `buffer_size` bytes at once
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Lets up to `queue_depth` chunks wait in front of each stage
+    pub fn queue_depth(mut self, queue_depth: usize) -> Self {
+        self.queue_depth = queue_depth.max(1);
+        self
+    }
+
+    /// Names the transfer in the progress display, see `Stats::label`
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Sets how sizes and rates are formatted in the progress display
+    pub fn units(mut self, units: Units) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Gives up with `PipelineError::Stalled` when no data comes through for `timeout`.
+    /// The stages blocked on their input are left behind, so this is meant for
+    /// processes that exit right after.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Takes `remote::Command`s on a Unix socket at `path` while running.
+    /// When the socket can't be created the copy goes on without it.
+    #[cfg(unix)]
+    pub fn remote(mut self, path: impl Into<PathBuf>) -> Self {
+        self.remote = Some(path.into());
+        self
+    }
+
+    /// Appends a transform running on its own thread before the writers,
+    /// the stats then show the output total and ratio as well
+    pub fn transform<T: Transform + 'static>(mut self, transform: T) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Lets the kernel copy a single file or pipe input to a single file or pipe output
+    /// on Linux. Only used when counting bytes without a checksum, enabled by default.
+    pub fn splice(mut self, splice: bool) -> Self {
+        self.splice = splice;
+        self
+    }
+
+    /// Runs the pipeline to completion and returns the error of the earliest failing stage
+    pub fn run(self) -> Result<()> {
+        let Pipeline {
+            inputs,
+            outputs,
+            failed_outputs,
+            observers,
+            rate_limit,
+            size,
+            line_mode,
+            drop_failed,
+            checksum,
+            checksum_file,
+            buffer_size,
+            queue_depth,
+            splice,
+            transforms,
+            label,
+            units,
+            stall_timeout,
+            #[cfg(unix)]
+            remote,
+            range,
+            skip_errors,
+            warn,
+        } = self;
+        let mut dropped = Dropped::new(warn.clone());
+        for e in failed_outputs {
+            if !drop_failed {
+                return Err(e);
+            }
+            dropped.push(e);
+        }
+        // no point in reading what can't be written anywhere
+        if outputs.is_empty() && dropped.any() {
+            return dropped.finish(false, Ok(()));
+        }
+        // input sizes are in bytes, so they only make a default when counting bytes,
+        // and the progress is about the range being copied
+        let size = if line_mode {
+            size
+        } else {
+            size.or_else(|| {
+                let available = inputs
+                    .iter()
+                    .map(Input::size)
+                    .sum::<Option<usize>>()
+                    .map(|total| total.saturating_sub(range.skip));
+                match (available, range.limit) {
+                    (Some(available), Some(limit)) => Some(available.min(limit)),
+                    (available, limit) => available.or(limit),
+                }
+            })
+        };
+
+        // the stats thread sees every chunk, so it has to apply back pressure like the writers
+        let (stats_tx, stats_rx) = bounded(queue_depth);
+        let (write_tx, write_rx) = bounded(queue_depth);
+        let pool = BufferPool::new(buffer_size, queue_depth);
+
+        // the read and stats threads both get the remote commands
+        let mut throttle = Throttle::new(rate_limit);
+        let mut control = None;
+        #[cfg(unix)]
+        let _server = match remote {
+            Some(path) => {
+                let (read_control_tx, read_control_rx) = unbounded();
+                let (stats_control_tx, stats_control_rx) = unbounded();
+                // the copy itself doesn't need the socket, e.g. with an unusable TMPDIR
+                match remote::Server::start(path, vec![read_control_tx, stats_control_tx]) {
+                    Ok(server) => {
+                        throttle = throttle.with_control(read_control_rx);
+                        control = Some(stats_control_rx);
+                        Some(server)
+                    }
+                    Err(e) => {
+                        warn(&Warning::NoRemoteControl(e));
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        // each transform gets its own channel, the reader feeds the first one
+        let mut read_tx = write_tx;
+        let mut transform_handles = Vec::with_capacity(transforms.len());
+        for transform in transforms.into_iter().rev() {
+            let (tx, rx) = bounded(queue_depth);
+            let pool = BufferPool::new(buffer_size, queue_depth);
+            let next_tx = mem::replace(&mut read_tx, tx);
+            let stats_tx = if transform_handles.is_empty() {
+                Some(stats_tx.clone())
+            } else {
+                None
+            };
+            let handle = thread::spawn(move || {
+                transform::transform_loop(transform, pool, rx, stats_tx, next_tx)
+            });
+            transform_handles.push(handle);
+        }
+
+        // the kernel copy bypasses the stats thread, which then only gets byte counts
+        #[cfg(target_os = "linux")]
+        let splice_to = match (&inputs[..], &outputs[..]) {
+            ([_], [output])
+                if splice
+                    && !skip_errors
+                    && !line_mode
+                    && checksum.is_none()
+                    && transform_handles.is_empty() =>
+            {
+                output.raw_fd().map(|fd| (fd, output.name().to_string()))
+            }
+            _ => None,
+        };
+        #[cfg(not(target_os = "linux"))]
+        let _ = splice;
+
+        let read_handle = thread::spawn(move || {
+            let (mut inputs, mut range) = (inputs, range);
+            // the kernel copy only works once the skipped part is seeked over
+            if let Some(input) = inputs.first_mut() {
+                range.skip -= input.skip(range.skip)?;
+            }
+            #[cfg(target_os = "linux")]
+            if let Some((fd, name)) = splice_to {
+                if range.skip == 0 {
+                    let spliced = splice::splice_loop(
+                        &inputs[0],
+                        fd,
+                        &name,
+                        range.limit,
+                        &mut throttle,
+                        &stats_tx,
+                        &read_tx,
+                    )?;
+                    if spliced {
+                        return Ok(());
+                    }
+                }
+            }
+            read::read_loop(
+                inputs,
+                pool,
+                range,
+                skip_errors,
+                throttle,
+                stats_tx,
+                read_tx,
+            )
+        });
+        let mut timer = Stats::new(rate_limit, size, line_mode).with_units(units);
+        if let Some(label) = label {
+            timer = timer.with_label(label);
+        }
+        let stats_handle = thread::spawn(move || {
+            stats::stats_loop(
+                timer,
+                checksum,
+                checksum_file,
+                stall_timeout,
+                control,
+                observers,
+                stats_rx,
+            )
+        });
+        let write_handle = thread::spawn(move || {
+            write::tee_loop(outputs, drop_failed, queue_depth, dropped, write_rx)
+        });
+
+        // `.join()` returns a `thread::Result<Result<()>>`, a panic becomes an error as well
+        let stats_result = stats_handle
+            .join()
+            .unwrap_or(Err(PipelineError::Panicked("stats")));
+        // the other stages may be blocked on a stalled input or output for good
+        if let Err(e @ PipelineError::Stalled(_)) | Err(e @ PipelineError::Interrupted(_)) =
+            stats_result
+        {
+            return Err(e);
+        }
+        let read_result = read_handle
+            .join()
+            .unwrap_or(Err(PipelineError::Panicked("read")));
+        // joined upstream first, so the earliest failing transform wins
+        let transform_result = transform_handles
+            .into_iter()
+            .rev()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or(Err(PipelineError::Panicked("transform")))
+            })
+            .fold(Ok(()), Result::and);
+        let write_result = write_handle
+            .join()
+            .unwrap_or(Err(PipelineError::Panicked("write")));
+
+        // a downstream process killed by the same Ctrl-C is not the cause either
+        if let Some(signal) = signal::received() {
+            return Err(PipelineError::Interrupted(signal));
+        }
+        // the earliest failing stage is the cause, later ones only saw it disconnect
+        read_result
+            .and(transform_result)
+            .and(write_result)
+            .and(stats_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pipeline;
+    use crate::error::{PipelineError, Warning};
+    use crate::read::Input;
+    use crate::stats::Stats;
+    use crate::write::Output;
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Writer whose contents can be inspected after the pipeline finished
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn copies_inputs_to_every_output() {
+        let (first, second) = (Shared::default(), Shared::default());
+        let totals = Arc::new(Mutex::new(Vec::new()));
+        let observed = totals.clone();
+        Pipeline::new()
+            .input(Input::new("a", Cursor::new(b"hello ".to_vec())))
+            .input(Input::new("b", Cursor::new(b"world".to_vec())))
+            .output(Output::new("first", first.clone()))
+            .output(Output::new("second", second.clone()))
+            .observer(move |stats: &Stats| observed.lock().unwrap().push(stats.total()))
+            .run()
+            .unwrap();
+        assert_eq!(first.0.lock().unwrap().as_slice(), b"hello world");
+        assert_eq!(second.0.lock().unwrap().as_slice(), b"hello world");
+        assert_eq!(totals.lock().unwrap().last(), Some(&11));
+    }
+
+    /// Reader that takes far longer than the stall timeout to come up with nothing
+    struct Stuck;
+
+    impl Read for Stuck {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_secs(5));
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn gives_up_on_a_stalled_input() {
+        let stalled = Arc::new(Mutex::new(false));
+        let observed = stalled.clone();
+        let result = Pipeline::new()
+            .input(Input::new("stuck", Stuck))
+            .output(Output::new("out", Shared::default()))
+            .stall_timeout(Duration::from_millis(100))
+            .observer(move |stats: &Stats| *observed.lock().unwrap() |= stats.is_stalled())
+            .run();
+        assert!(matches!(result, Err(PipelineError::Stalled(_))));
+        assert!(*stalled.lock().unwrap());
+    }
+
+    /// Reader and writer failing at once, like a disk going away
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("device gone"))
+        }
+    }
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("device gone"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reports_read_errors() {
+        let result = Pipeline::new()
+            .input(Input::new("broken", Broken))
+            .output(Output::new("out", Shared::default()))
+            .run();
+        match result {
+            Err(PipelineError::Read { name, .. }) => assert_eq!(name, "broken"),
+            other => panic!("expected a read error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_write_errors() {
+        let result = Pipeline::new()
+            .input(Input::new("in", Cursor::new(b"hello".to_vec())))
+            .output(Output::new("broken", Broken))
+            .run();
+        match result {
+            Err(PipelineError::Write { name, .. }) => assert_eq!(name, "broken"),
+            other => panic!("expected a write error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn drops_failed_outputs() {
+        let healthy = Shared::default();
+        let data = b"pipeviewer ".repeat(1000);
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let warned = warnings.clone();
+        Pipeline::new()
+            .input(Input::new("in", Cursor::new(data.clone())))
+            .output(Output::new("broken", Broken))
+            .output(Output::new("healthy", healthy.clone()))
+            .buffer_size(64)
+            .drop_failed(true)
+            .on_warning(move |warning| warned.lock().unwrap().push(warning.to_string()))
+            .run()
+            .unwrap();
+        assert_eq!(healthy.0.lock().unwrap().as_slice(), data.as_slice());
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec![String::from("writing broken: device gone, dropping output")]
+        );
+    }
+
+    #[test]
+    fn fails_once_every_output_is_dropped() {
+        let result = Pipeline::new()
+            .input(Input::new("in", Cursor::new(b"hello".to_vec())))
+            .output(Output::new("first", Broken))
+            .output(Output::new("second", Broken))
+            .drop_failed(true)
+            .run();
+        assert!(matches!(result, Err(PipelineError::Write { .. })));
+    }
+
+    #[test]
+    fn drops_outputs_that_failed_to_open() {
+        let unopened = || Output::create("/nonexistent/out");
+        let healthy = Shared::default();
+        let warnings = Arc::new(Mutex::new(0));
+        let warned = warnings.clone();
+        Pipeline::new()
+            .input(Input::new("in", Cursor::new(b"hello".to_vec())))
+            .try_output(unopened())
+            .try_output(Ok(Output::new("healthy", healthy.clone())))
+            .drop_failed(true)
+            .on_warning(move |_| *warned.lock().unwrap() += 1)
+            .run()
+            .unwrap();
+        assert_eq!(healthy.0.lock().unwrap().as_slice(), b"hello");
+        assert_eq!(*warnings.lock().unwrap(), 1);
+
+        let result = Pipeline::new()
+            .input(Input::new("in", Cursor::new(b"hello".to_vec())))
+            .try_output(unopened())
+            .try_output(unopened())
+            .drop_failed(true)
+            .run();
+        assert!(matches!(result, Err(PipelineError::Write { .. })));
+    }
+
+    #[test]
+    fn sends_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let sender = thread::spawn(move || {
+            Pipeline::new()
+                .input(Input::new("data", Cursor::new(b"over the wire".to_vec())))
+                .output(Output::connect(&addr).unwrap())
+                .run()
+        });
+        let received = Shared::default();
+        Pipeline::new()
+            .input(Input::accept(&listener).unwrap())
+            .output(Output::new("received", received.clone()))
+            .run()
+            .unwrap();
+        sender.join().unwrap().unwrap();
+        assert_eq!(received.0.lock().unwrap().as_slice(), b"over the wire");
+    }
+
+    #[test]
+    fn copies_only_the_range() {
+        let out = Shared::default();
+        let percentages = Arc::new(Mutex::new(Vec::new()));
+        let observed = percentages.clone();
+        Pipeline::new()
+            .input(Input::new("a", Cursor::new(b"hello ".to_vec())))
+            .input(Input::new("b", Cursor::new(b"world".to_vec())))
+            .output(Output::new("out", out.clone()))
+            // chunks straddle both ends of the range
+            .buffer_size(4)
+            .skip(3)
+            .stop_at(5)
+            .observer(move |stats: &Stats| observed.lock().unwrap().push(stats.percentage()))
+            .run()
+            .unwrap();
+        assert_eq!(out.0.lock().unwrap().as_slice(), b"lo wo");
+        assert_eq!(percentages.lock().unwrap().last(), Some(&Some(100.0)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_without_a_remote_socket() {
+        let output = Shared::default();
+        let warned = Arc::new(Mutex::new(false));
+        let observed = warned.clone();
+        Pipeline::new()
+            .input(Input::new("in", Cursor::new(b"hello".to_vec())))
+            .output(Output::new("out", output.clone()))
+            .remote("/nonexistent/pipeviewer-remote")
+            .on_warning(move |warning| {
+                *observed.lock().unwrap() = matches!(warning, Warning::NoRemoteControl(_))
+            })
+            .run()
+            .unwrap();
+        assert_eq!(output.0.lock().unwrap().as_slice(), b"hello");
+        assert!(*warned.lock().unwrap());
+    }
+}
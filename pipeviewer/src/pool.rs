@@ -0,0 +1,114 @@
+//! Recycled buffers, so the read loop does not allocate a new `Vec` per chunk.
+//!
+//! A [`Buffer`] travels from the reader to the stats and write stages inside an
+//! `Arc` and goes back to its pool over a channel when the last stage drops it.
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A filled buffer shared between the stages
+pub type Chunk = Arc<Buffer>;
+
+pub struct BufferPool {
+    buffer_size: usize,
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Creates a pool of `buffer_size` byte buffers keeping at most `capacity` spare ones
+    pub fn new(buffer_size: usize, capacity: usize) -> Self {
+        let (tx, rx) = bounded(capacity);
+        Self {
+            buffer_size,
+            tx,
+            rx,
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Takes a returned buffer, allocating a new one when none is available
+    pub fn take(&self) -> Buffer {
+        let data = self
+            .rx
+            .try_recv()
+            .unwrap_or_else(|_| vec![0; self.buffer_size]);
+        Buffer {
+            data,
+            len: 0,
+            home: self.tx.clone(),
+        }
+    }
+}
+
+/// A buffer that returns to its pool when dropped
+pub struct Buffer {
+    data: Vec<u8>,
+    len: usize,
+    home: Sender<Vec<u8>>,
+}
+
+impl Buffer {
+    /// The whole buffer, to be filled and then marked with `set_len`
+    pub fn space(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Sets how many bytes at the start of the buffer are valid
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.data.len());
+        self.len = len;
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // a full pool just lets the buffer be freed
+        let _ = self.home.try_send(mem::take(&mut self.data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+    use std::sync::Arc;
+
+    #[test]
+    fn buffers_are_recycled() {
+        let pool = BufferPool::new(8, 2);
+        let mut buffer = pool.take();
+        buffer.space()[..3].copy_from_slice(b"abc");
+        buffer.set_len(3);
+        let chunk = Arc::new(buffer);
+        let shared = chunk.clone();
+        assert_eq!(&chunk[..], b"abc");
+        let address = chunk.as_ptr();
+        drop(chunk);
+        drop(shared);
+        let mut again = pool.take();
+        assert_eq!(again.space().as_ptr(), address);
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn full_pool_frees_buffers() {
+        let pool = BufferPool::new(8, 1);
+        let (first, second) = (pool.take(), pool.take());
+        drop(first);
+        drop(second);
+        assert_eq!(pool.rx.len(), 1);
+    }
+}
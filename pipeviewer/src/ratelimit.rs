@@ -0,0 +1,177 @@
+//! Token bucket used to throttle the read loop, similar to `pv -L`.
+
+use super::remote::Command;
+use super::signal;
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a paused reader checks for signals
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+/// Time worth of tokens the limiter saves up while idle
+const BURST: Duration = Duration::from_millis(100);
+
+pub struct RateLimiter {
+    rate: f64,
+    /// Most tokens saved up while idle
+    burst: f64,
+    tokens: f64,
+    last_instant: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `rate` bytes per second. It starts out empty and
+    /// saves up at most a tenth of a second, so that even the first second keeps to
+    /// the rate. A zero rate would never refill, it is raised to one byte per second.
+    pub fn new(rate: usize) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            burst: rate * BURST.as_secs_f64(),
+            tokens: 0.0,
+            last_instant: Instant::now(),
+        }
+    }
+
+    /// Largest chunk that should be read at once so that the display stays responsive.
+    pub fn chunk_size(&self, max: usize) -> usize {
+        (self.rate as usize).clamp(1, max)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.last_instant;
+        self.last_instant = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+    }
+
+    /// Takes `amount` bytes worth of tokens, sleeping until enough are available.
+    pub fn consume(&mut self, amount: usize) {
+        self.refill();
+        // a chunk larger than the burst leaves a debt, which the sleep pays off
+        self.tokens -= amount as f64;
+        if self.tokens < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-self.tokens / self.rate));
+            self.refill();
+        }
+    }
+}
+
+/// A limiter for `rate`, none when there is no rate or it is 0
+fn new_limiter(rate: Option<usize>) -> Option<RateLimiter> {
+    rate.filter(|&rate| rate > 0).map(RateLimiter::new)
+}
+
+/// The rate limit of the read side, which remote commands change and pause
+pub struct Throttle {
+    limiter: Option<RateLimiter>,
+    paused: bool,
+    control: Option<Receiver<Command>>,
+}
+
+impl Throttle {
+    pub fn new(rate_limit: Option<usize>) -> Self {
+        Self {
+            limiter: new_limiter(rate_limit),
+            paused: false,
+            control: None,
+        }
+    }
+
+    /// Takes the rate limit and pause commands from `control`
+    pub fn with_control(mut self, control: Receiver<Command>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Largest chunk that should be read at once, see `RateLimiter::chunk_size`
+    pub fn chunk_size(&self, max: usize) -> usize {
+        self.limiter
+            .as_ref()
+            .map_or(max, |limiter| limiter.chunk_size(max))
+    }
+
+    /// Applies the pending commands and blocks while paused, or until a signal arrives
+    pub fn wait(&mut self) {
+        let Self {
+            limiter,
+            paused,
+            control,
+        } = self;
+        let control = match control {
+            Some(control) => control,
+            None => return,
+        };
+        loop {
+            let command = if *paused {
+                match control.recv_timeout(PAUSE_POLL) {
+                    Ok(command) => command,
+                    Err(RecvTimeoutError::Timeout) if signal::received().is_none() => continue,
+                    Err(_) => return,
+                }
+            } else {
+                match control.try_recv() {
+                    Ok(command) => command,
+                    Err(_) => return,
+                }
+            };
+            match command {
+                Command::RateLimit(rate) => *limiter = new_limiter(rate),
+                Command::Pause => *paused = true,
+                Command::Resume => *paused = false,
+                Command::Format(_) => (),
+            }
+        }
+    }
+
+    /// Takes `amount` bytes from the rate limit, if there is one
+    pub fn consume(&mut self, amount: usize) {
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.consume(amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, Throttle};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn consume_throttles_from_a_cold_start() {
+        let mut limiter = RateLimiter::new(10_000);
+        let start = Instant::now();
+        // five tenths of a second worth, the first second must not go faster
+        for _ in 0..5 {
+            limiter.consume(1_000);
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(480), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(700), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn consume_saves_up_little_while_idle() {
+        let mut limiter = RateLimiter::new(10_000);
+        std::thread::sleep(Duration::from_millis(300));
+        let start = Instant::now();
+        limiter.consume(1_000);
+        assert!(start.elapsed() < Duration::from_millis(50));
+        limiter.consume(1_000);
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn zero_rate_is_no_limit() {
+        let mut throttle = Throttle::new(Some(0));
+        let start = Instant::now();
+        throttle.consume(1_000_000);
+        assert_eq!(throttle.chunk_size(4096), 4096);
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // raised to one byte per second rather than dividing by zero
+        let mut limiter = RateLimiter::new(0);
+        limiter.consume(0);
+        assert_eq!(limiter.chunk_size(4096), 1);
+    }
+}
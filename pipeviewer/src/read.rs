@@ -0,0 +1,426 @@
+use super::error::{PipelineError, Result};
+use super::pool::BufferPool;
+use super::ratelimit::Throttle;
+use super::signal;
+use super::stats::StatsMessage;
+use super::write::WriteMessage;
+use crossbeam::channel::Sender;
+use std::fs::{File, Metadata};
+use std::io::{self, BufReader, ErrorKind, Read, Result as IoResult, Seek, SeekFrom};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    io::{AsRawFd, RawFd},
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Bytes given up at a time past a read error, a disk sector
+const ERROR_BLOCK: usize = 512;
+/// How often a listening input checks for signals while nobody connects
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+// pub fn read_input(infile: &str) -> IoResult<Vec<u8>> {
+//     let mut reader: Box<dyn Read> = if !infile.is_empty() {
+//         Box::new(BufReader::new(File::open(infile)?))
+//     } else {
+//         Box::new(BufReader::new(io::stdin()))
+//     };
+//     let mut buffer = [0; CHUNK_SIZE];
+//     let num_read = reader.read(&mut buffer)?;
+//     Ok(Vec::from(&buffer[..num_read]))
+// }
+
+/// A named source of data for the read loop
+pub struct Input {
+    name: String,
+    size: Option<usize>,
+    reader: Box<dyn Read + Send>,
+    /// Descriptor behind `reader` for the splice fast path, set when nothing buffers in between
+    #[cfg(unix)]
+    fd: Option<RawFd>,
+    /// Handle sharing the offset of a regular file or block device behind `reader`,
+    /// for skipping by seeking
+    file: Option<File>,
+}
+
+/// The part of the stream that is copied, in bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Range {
+    /// Bytes dropped from the start
+    pub skip: usize,
+    /// Bytes copied after those, all of them when `None`
+    pub limit: Option<usize>,
+}
+
+impl Input {
+    pub fn new<R: Read + Send + 'static>(name: impl Into<String>, reader: R) -> Self {
+        Self {
+            name: name.into(),
+            size: None,
+            reader: Box::new(reader),
+            #[cfg(unix)]
+            fd: None,
+            file: None,
+        }
+    }
+
+    /// Sets the number of bytes this input is expected to provide
+    pub fn with_size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn stdin() -> Self {
+        let stdin = io::stdin();
+        #[cfg(unix)]
+        let fd = Some(stdin.as_raw_fd());
+        Self {
+            #[cfg(unix)]
+            fd,
+            ..Self::new("stdin", BufReader::new(stdin))
+        }
+    }
+
+    /// Opens `path` for reading, `-` or an empty path being stdin.
+    /// Regular files and block devices get their size set.
+    pub fn open(path: &str) -> Result<Self> {
+        if path.is_empty() || path == "-" {
+            return Ok(Self::stdin());
+        }
+        let open = || -> IoResult<Self> {
+            let mut file = File::open(path)?;
+            let meta = file.metadata()?;
+            #[cfg(unix)]
+            let fd = Some(file.as_raw_fd());
+            // a block device reports no length, but can be seeked to its end
+            let size = if meta.is_file() {
+                Some(meta.len() as usize)
+            } else if is_block_device(&meta) {
+                let size = file.seek(SeekFrom::End(0))?;
+                file.seek(SeekFrom::Start(0))?;
+                Some(size as usize)
+            } else {
+                None
+            };
+            let seekable = match size {
+                Some(_) => Some(file.try_clone()?),
+                None => None,
+            };
+            let input = Self {
+                #[cfg(unix)]
+                fd,
+                file: seekable,
+                ..Self::new(path, BufReader::new(file))
+            };
+            Ok(match size {
+                Some(size) => input.with_size(size),
+                None => input,
+            })
+        };
+        open().map_err(|source| PipelineError::Read {
+            name: path.to_string(),
+            source,
+        })
+    }
+
+    /// Skips up to `bytes` by seeking when this is a regular file and returns how many,
+    /// the read loop reads and drops the rest. Only meant for before the first read.
+    pub fn skip(&mut self, bytes: usize) -> Result<usize> {
+        let (file, size) = match (self.file.as_mut(), self.size.as_mut()) {
+            (Some(file), Some(size)) => (file, size),
+            _ => return Ok(0),
+        };
+        let skipped = bytes.min(*size);
+        if let Err(source) = file.seek(SeekFrom::Current(skipped as i64)) {
+            return Err(PipelineError::Read {
+                name: self.name.clone(),
+                source,
+            });
+        }
+        *size -= skipped;
+        Ok(skipped)
+    }
+
+    /// Waits for one connection on `addr`, e.g. `0.0.0.0:9000`, and reads what it sends
+    pub fn listen(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr).map_err(|source| PipelineError::Listen {
+            addr: addr.to_string(),
+            source,
+        })?;
+        Self::accept(&listener)
+    }
+
+    /// Reads from the next connection made to `listener`. The wait ends early with
+    /// `PipelineError::Interrupted` on SIGINT or SIGTERM.
+    pub fn accept(listener: &TcpListener) -> Result<Self> {
+        let failed = |source| PipelineError::Read {
+            name: listener
+                .local_addr()
+                .map_or_else(|_| String::from("tcp"), |addr| format!("tcp:{}", addr)),
+            source,
+        };
+        // a blocking accept would retry through the signal
+        listener.set_nonblocking(true).map_err(failed)?;
+        let accepted = loop {
+            match listener.accept() {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => match signal::received() {
+                    Some(signal) => break Err(PipelineError::Interrupted(signal)),
+                    None => thread::sleep(ACCEPT_POLL),
+                },
+                accepted => break accepted.map_err(failed),
+            }
+        };
+        listener.set_nonblocking(false).map_err(failed)?;
+        let (stream, peer) = accepted?;
+        stream.set_nonblocking(false).map_err(failed)?;
+        #[cfg(unix)]
+        let fd = Some(stream.as_raw_fd());
+        Ok(Self {
+            #[cfg(unix)]
+            fd,
+            ..Self::new(format!("tcp:{}", peer), stream)
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    #[cfg(unix)]
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+}
+
+#[cfg(unix)]
+fn is_block_device(meta: &Metadata) -> bool {
+    meta.file_type().is_block_device()
+}
+
+#[cfg(not(unix))]
+fn is_block_device(_meta: &Metadata) -> bool {
+    false
+}
+
+/// Whether `error` is the medium failing to read (EIO), which `--skip-errors` gets past
+#[cfg(unix)]
+fn unreadable(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EIO)
+}
+
+#[cfg(not(unix))]
+fn unreadable(_error: &io::Error) -> bool {
+    false
+}
+
+/// Streams the `range` of `inputs`, concatenated in order, to the write loop,
+/// reading into buffers taken from `pool`.
+///
+/// With `skip_errors`, a regular file or block device failing with EIO is seeked
+/// past the bad block, which is sent on as zeros and reported as `StatsMessage::Skipped`.
+pub fn read_loop(
+    inputs: Vec<Input>,
+    pool: BufferPool,
+    range: Range,
+    skip_errors: bool,
+    mut throttle: Throttle,
+    stats_tx: Sender<StatsMessage>,
+    write_tx: Sender<WriteMessage>,
+) -> Result<()> {
+    let count = inputs.len();
+    let Range {
+        mut skip,
+        limit: mut remaining,
+    } = range;
+    'inputs: for (index, mut input) in inputs.into_iter().enumerate() {
+        if remaining == Some(0) {
+            break;
+        }
+        skip -= input.skip(skip)?;
+        let Input {
+            name,
+            size,
+            mut reader,
+            mut file,
+            ..
+        } = input;
+        let _ = stats_tx.send(StatsMessage::Input {
+            name: name.clone(),
+            size,
+            index,
+            count,
+        });
+        let mut position = 0;
+        loop {
+            throttle.wait();
+            // on a signal the writers still get `Done` and flush what they have
+            if signal::received().is_some() {
+                break 'inputs;
+            }
+            let chunk_size = match remaining {
+                // the end of the range is only known once the skipping is done
+                Some(left) if skip == 0 => throttle.chunk_size(pool.buffer_size()).min(left),
+                _ => throttle.chunk_size(pool.buffer_size()),
+            };
+            let mut buffer = pool.take();
+            let mut num_read = match reader.read(&mut buffer.space()[..chunk_size]) {
+                Ok(0) => break,
+                Ok(x) => x,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(source) => match file.as_mut().filter(|_| skip_errors && unreadable(&source)) {
+                    Some(file) => {
+                        let left = size.map_or(0, |size| size.saturating_sub(position));
+                        let bad = ERROR_BLOCK.min(chunk_size).min(left);
+                        // nothing left to skip, the error is not about a block of data
+                        if bad == 0 {
+                            return Err(PipelineError::Read { name, source });
+                        }
+                        if let Err(source) = file.seek(SeekFrom::Current(bad as i64)) {
+                            return Err(PipelineError::Read { name, source });
+                        }
+                        buffer.space()[..bad].fill(0);
+                        let _ = stats_tx.send(StatsMessage::Skipped(bad));
+                        bad
+                    }
+                    // dropping the senders without `Done` tells the other stages to stop
+                    None => return Err(PipelineError::Read { name, source }),
+                },
+            };
+            position += num_read;
+            if skip > 0 {
+                // a chunk straddling the start of the range keeps its tail
+                let dropped = skip.min(num_read);
+                skip -= dropped;
+                buffer.space().copy_within(dropped..num_read, 0);
+                num_read -= dropped;
+                if num_read == 0 {
+                    continue;
+                }
+            }
+            if let Some(left) = remaining.as_mut() {
+                num_read = num_read.min(*left);
+                *left -= num_read;
+            }
+            throttle.consume(num_read);
+            buffer.set_len(num_read);
+            let chunk = Arc::new(buffer);
+            let _ = stats_tx.send(StatsMessage::Chunk(chunk.clone()));
+            if write_tx.send(WriteMessage::Chunk(chunk)).is_err() || remaining == Some(0) {
+                break 'inputs;
+            }
+        }
+    }
+    let _ = stats_tx.send(StatsMessage::Done);
+    let _ = write_tx.send(WriteMessage::Done);
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{read_loop, Input, Range};
+    use crate::error::Result;
+    use crate::pool::BufferPool;
+    use crate::ratelimit::Throttle;
+    use crate::stats::StatsMessage;
+    use crate::write::WriteMessage;
+    use crossbeam::channel::unbounded;
+    use std::fs::{self, File};
+    use std::io::{self, Read, Seek};
+
+    /// Fails once with `errno` when reading at `bad`, like a flaky sector
+    struct Flaky {
+        file: File,
+        bad: u64,
+        errno: Option<i32>,
+    }
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.file.stream_position()? == self.bad {
+                if let Some(errno) = self.errno.take() {
+                    return Err(io::Error::from_raw_os_error(errno));
+                }
+            }
+            self.file.read(buf)
+        }
+    }
+
+    /// Reads 2048 bytes of ones failing at `bad`, returning the result,
+    /// the data written and the bytes reported as skipped
+    fn read_flaky(bad: u64, errno: i32) -> (Result<()>, Vec<u8>, usize) {
+        let path = std::env::temp_dir().join(format!(
+            "pipeviewer-read-{}-{}-{}",
+            std::process::id(),
+            bad,
+            errno
+        ));
+        fs::write(&path, vec![1; 2048]).unwrap();
+        let file = File::open(&path).unwrap();
+        let flaky = Flaky {
+            file: file.try_clone().unwrap(),
+            bad,
+            errno: Some(errno),
+        };
+        let input = Input {
+            file: Some(file),
+            ..Input::new("flaky", flaky).with_size(2048)
+        };
+
+        let (stats_tx, stats_rx) = unbounded();
+        let (write_tx, write_rx) = unbounded();
+        let result = read_loop(
+            vec![input],
+            BufferPool::new(512, 8),
+            Range::default(),
+            true,
+            Throttle::new(None),
+            stats_tx,
+            write_tx,
+        );
+        let _ = fs::remove_file(&path);
+
+        let mut copied = Vec::new();
+        for message in write_rx.try_iter() {
+            if let WriteMessage::Chunk(chunk) = message {
+                copied.extend_from_slice(&chunk);
+            }
+        }
+        let skipped = stats_rx
+            .try_iter()
+            .map(|message| match message {
+                StatsMessage::Skipped(num) => num,
+                _ => 0,
+            })
+            .sum();
+        (result, copied, skipped)
+    }
+
+    #[test]
+    fn skips_unreadable_blocks() {
+        let (result, copied, skipped) = read_flaky(512, libc::EIO);
+        assert!(result.is_ok());
+        let mut expected = vec![1; 2048];
+        expected[512..1024].fill(0);
+        assert_eq!(copied, expected);
+        assert_eq!(skipped, 512);
+    }
+
+    #[test]
+    fn stops_on_other_errors() {
+        // only EIO means a bad block
+        let (result, _, skipped) = read_flaky(512, libc::EACCES);
+        assert!(result.is_err());
+        assert_eq!(skipped, 0);
+        // past the end there is no block to skip
+        let (result, copied, skipped) = read_flaky(2048, libc::EIO);
+        assert!(result.is_err());
+        assert_eq!((copied.len(), skipped), (2048, 0));
+    }
+}
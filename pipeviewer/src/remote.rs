@@ -0,0 +1,205 @@
+//! Control of a running pipeviewer from another one, like `pv -R`.
+//!
+//! Every instance listens on a Unix socket named after its pid. A client connects,
+//! writes one command per line and reads back `ok` or `error: ...` for each. The
+//! commands are forwarded to the read and stats threads.
+
+use super::error::{PipelineError, Result};
+use super::template::Template;
+use super::units::parse_size;
+use std::fmt;
+use std::str::FromStr;
+#[cfg(unix)]
+use {
+    super::listener::Acceptor,
+    crossbeam::channel::Sender,
+    std::fs::{self, Permissions},
+    std::io::{self, BufRead, BufReader, ErrorKind, Write},
+    std::os::unix::fs::PermissionsExt,
+    std::os::unix::net::{UnixListener, UnixStream},
+    std::path::PathBuf,
+    std::time::Duration,
+};
+
+/// A change to a running pipeline
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// New rate limit in bytes per second, `None` lifting it
+    RateLimit(Option<usize>),
+    /// New terminal line layout
+    Format(Template),
+    Pause,
+    Resume,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::RateLimit(Some(rate)) => write!(f, "rate-limit {}", rate),
+            Command::RateLimit(None) => write!(f, "rate-limit none"),
+            Command::Format(template) => write!(f, "format {}", template),
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, argument) = s.split_once(' ').unwrap_or((s, ""));
+        match name {
+            "rate-limit" => match argument.trim() {
+                "none" | "0" => Ok(Command::RateLimit(None)),
+                rate => parse_size(rate)
+                    .map(|rate| Command::RateLimit(Some(rate).filter(|&rate| rate > 0))),
+            },
+            "format" => argument
+                .parse()
+                .map(Command::Format)
+                .map_err(|e| e.to_string()),
+            "pause" => Ok(Command::Pause),
+            "resume" => Ok(Command::Resume),
+            _ => Err(format!("unknown command `{}`", name)),
+        }
+    }
+}
+
+/// Socket of the instance running as `pid`
+#[cfg(unix)]
+pub fn socket_path(pid: u32) -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("pipeviewer-remote-{}-{}", uid, pid))
+}
+
+/// Accepts commands on a socket and forwards them to every target, until dropped
+#[cfg(unix)]
+pub struct Server {
+    path: PathBuf,
+    acceptor: Acceptor,
+}
+
+#[cfg(unix)]
+impl Server {
+    pub fn start(path: PathBuf, targets: Vec<Sender<Command>>) -> Result<Self> {
+        let failed = |source| PipelineError::Control {
+            name: path.display().to_string(),
+            source,
+        };
+        // left behind by an instance that was killed, pids get reused
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).map_err(failed)?;
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).map_err(failed)?;
+        listener.set_nonblocking(true).map_err(failed)?;
+        let acceptor = Acceptor::spawn(
+            move || listener.accept().map(|(stream, _)| stream),
+            move |stream| {
+                let _ = serve(stream, &targets);
+            },
+        );
+        Ok(Self { path, acceptor })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.acceptor.stop();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Answers the commands of one client
+#[cfg(unix)]
+fn serve(stream: UnixStream, targets: &[Sender<Command>]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    // a client that doesn't say anything must not keep the others out
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let reply = match line?.parse::<Command>() {
+            Ok(command) => {
+                for target in targets {
+                    let _ = target.send(command.clone());
+                }
+                String::from("ok")
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
+
+/// Sends `commands` to the instance running as `pid`, stopping at the first one it rejects
+#[cfg(unix)]
+pub fn send(pid: u32, commands: &[Command]) -> Result<()> {
+    let failed = |source| PipelineError::Control {
+        name: format!("process {}", pid),
+        source,
+    };
+    let stream = UnixStream::connect(socket_path(pid)).map_err(failed)?;
+    let mut writer = stream.try_clone().map_err(failed)?;
+    let mut replies = BufReader::new(stream).lines();
+    for command in commands {
+        writeln!(writer, "{}", command).map_err(failed)?;
+        let reply = replies
+            .next()
+            .unwrap_or_else(|| Err(ErrorKind::UnexpectedEof.into()))
+            .map_err(failed)?;
+        if let Some(message) = reply.strip_prefix("error: ") {
+            return Err(failed(io::Error::new(ErrorKind::InvalidInput, message)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn send(pid: u32, _commands: &[Command]) -> Result<()> {
+    Err(PipelineError::Control {
+        name: format!("process {}", pid),
+        source: std::io::Error::new(std::io::ErrorKind::Other, "needs Unix domain sockets"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    #[test]
+    fn commands_round_trip() {
+        let commands = vec![
+            Command::RateLimit(Some(1024)),
+            Command::RateLimit(None),
+            Command::Format("%b [%r] 50%%".parse().unwrap()),
+            Command::Pause,
+            Command::Resume,
+        ];
+        for command in commands {
+            assert_eq!(command.to_string().parse(), Ok(command));
+        }
+        assert_eq!("rate-limit 1K".parse(), Ok(Command::RateLimit(Some(1024))));
+        assert!("rate-limit fast".parse::<Command>().is_err());
+        assert!("stop".parse::<Command>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn server_forwards_commands() {
+        use super::{send, Server};
+        use crossbeam::channel::unbounded;
+
+        // this test process stands in for the instance being controlled
+        let pid = std::process::id();
+        let (tx, rx) = unbounded();
+        let server = Server::start(super::socket_path(pid), vec![tx]).unwrap();
+        send(pid, &[Command::Pause, Command::RateLimit(Some(512))]).unwrap();
+        drop(server);
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![Command::Pause, Command::RateLimit(Some(512))]
+        );
+        assert!(send(pid, &[Command::Resume]).is_err());
+    }
+}
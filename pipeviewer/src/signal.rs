@@ -0,0 +1,49 @@
+//! Ctrl-C and `kill` handling, turning SIGINT and SIGTERM into a clean shutdown.
+//!
+//! The handler only records the signal. The read loop stops at its next chunk and
+//! lets the writers flush, the stats loop draws the final line and the pipeline
+//! returns `PipelineError::Interrupted`. A second signal kills the process at once.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The first signal received, 0 while there is none
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// The signal that asked for a shutdown, if any
+pub fn received() -> Option<i32> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    if RECEIVED
+        .compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        // asked twice, stop waiting for the stages
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+/// Installs the handlers for SIGINT and SIGTERM. Without `SA_RESTART`, so that
+/// a blocking read on the thread taking the signal returns early.
+#[cfg(unix)]
+pub fn install() {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        for &signal in &[libc::SIGINT, libc::SIGTERM] {
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
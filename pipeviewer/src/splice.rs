@@ -0,0 +1,207 @@
+//! Linux fast path moving data between file descriptors inside the kernel.
+//!
+//! `splice(2)` is used when either end is a pipe and `copy_file_range(2)` between
+//! two regular files. Anything else goes through the read and write loops.
+
+use super::error::{PipelineError, Result};
+use super::ratelimit::Throttle;
+use super::read::Input;
+use super::signal;
+use super::stats::StatsMessage;
+use super::write::WriteMessage;
+use crossbeam::channel::Sender;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+/// Most bytes moved by a single call
+const SPLICE_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone, Copy)]
+enum Method {
+    Splice,
+    CopyFileRange,
+}
+
+fn file_type(fd: RawFd) -> io::Result<libc::mode_t> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.st_mode & libc::S_IFMT)
+}
+
+fn method(in_fd: RawFd, out_fd: RawFd) -> Option<Method> {
+    let from = file_type(in_fd).ok()?;
+    let to = file_type(out_fd).ok()?;
+    if from == libc::S_IFIFO || to == libc::S_IFIFO {
+        Some(Method::Splice)
+    } else if from == libc::S_IFREG && to == libc::S_IFREG {
+        Some(Method::CopyFileRange)
+    } else {
+        None
+    }
+}
+
+fn copy(method: Method, in_fd: RawFd, out_fd: RawFd, len: usize) -> io::Result<usize> {
+    let copied = unsafe {
+        match method {
+            Method::Splice => libc::splice(
+                in_fd,
+                ptr::null_mut(),
+                out_fd,
+                ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_MORE,
+            ),
+            Method::CopyFileRange => {
+                libc::copy_file_range(in_fd, ptr::null_mut(), out_fd, ptr::null_mut(), len, 0)
+            }
+        }
+    };
+    if copied < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(copied as usize)
+    }
+}
+
+/// Errors meaning the kernel can't move data between these two descriptors
+fn unsupported(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(libc::EINVAL)
+            | Some(libc::ENOSYS)
+            | Some(libc::EXDEV)
+            | Some(libc::EBADF)
+            | Some(libc::EOPNOTSUPP)
+    )
+}
+
+/// Copies `input` to `out_fd` without going through userspace, reporting the
+/// byte counts to the stats loop and sending `Done` to both stages at the end.
+///
+/// Returns `Ok(false)` before anything was copied when the descriptors don't
+/// support it, the caller then falls back to the read loop.
+pub fn splice_loop(
+    input: &Input,
+    out_fd: RawFd,
+    out_name: &str,
+    limit: Option<usize>,
+    throttle: &mut Throttle,
+    stats_tx: &Sender<StatsMessage>,
+    write_tx: &Sender<WriteMessage>,
+) -> Result<bool> {
+    let in_fd = match input.raw_fd() {
+        Some(fd) => fd,
+        None => return Ok(false),
+    };
+    let method = match method(in_fd, out_fd) {
+        Some(method) => method,
+        None => return Ok(false),
+    };
+    let mut started = false;
+    let mut broken = false;
+    let mut remaining = limit;
+    loop {
+        throttle.wait();
+        if signal::received().is_some() {
+            break;
+        }
+        let len = match remaining {
+            Some(0) => break,
+            Some(left) => throttle.chunk_size(SPLICE_SIZE).min(left),
+            None => throttle.chunk_size(SPLICE_SIZE),
+        };
+        let num_copied = match copy(method, in_fd, out_fd, len) {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if !started && unsupported(&e) => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                broken = true;
+                break;
+            }
+            Err(source) => {
+                return Err(PipelineError::Copy {
+                    from: input.name().to_string(),
+                    to: out_name.to_string(),
+                    source,
+                })
+            }
+        };
+        if !started {
+            started = true;
+            let _ = stats_tx.send(StatsMessage::Input {
+                name: input.name().to_string(),
+                size: input.size(),
+                index: 0,
+                count: 1,
+            });
+        }
+        if num_copied == 0 {
+            break;
+        }
+        if let Some(left) = remaining.as_mut() {
+            *left -= num_copied;
+        }
+        throttle.consume(num_copied);
+        let _ = stats_tx.send(StatsMessage::Count(num_copied));
+    }
+    let _ = stats_tx.send(StatsMessage::Done);
+    let _ = write_tx.send(WriteMessage::Done);
+    if broken {
+        return Err(PipelineError::BrokenPipe(out_name.to_string()));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::splice_loop;
+    use crate::ratelimit::Throttle;
+    use crate::read::Input;
+    use crate::stats::StatsMessage;
+    use crossbeam::channel::unbounded;
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn copies_file_to_file() {
+        let dir = std::env::temp_dir();
+        let from = dir.join(format!("pipeviewer-splice-{}-in", std::process::id()));
+        let to = dir.join(format!("pipeviewer-splice-{}-out", std::process::id()));
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        fs::write(&from, &data).unwrap();
+
+        let input = Input::open(from.to_str().unwrap()).unwrap();
+        let output = File::create(&to).unwrap();
+        let (stats_tx, stats_rx) = unbounded();
+        let (write_tx, write_rx) = unbounded();
+        let spliced = splice_loop(
+            &input,
+            output.as_raw_fd(),
+            "out",
+            None,
+            &mut Throttle::new(None),
+            &stats_tx,
+            &write_tx,
+        )
+        .unwrap();
+        let copied = fs::read(&to).unwrap();
+        let _ = fs::remove_file(&from);
+        let _ = fs::remove_file(&to);
+
+        assert!(spliced);
+        assert_eq!(copied, data);
+        let counted: usize = stats_rx
+            .try_iter()
+            .map(|message| match message {
+                StatsMessage::Count(num) => num,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(counted, data.len());
+        assert_eq!(write_rx.try_iter().count(), 1);
+    }
+}
@@ -0,0 +1,888 @@
+//! The stats module contains the stats loop that is performed on stats thread.
+//!
+use super::checksum::{Algorithm, Hasher};
+use super::error::{PipelineError, Result};
+use super::observer::Observer;
+use super::pool::Chunk;
+use super::remote::Command;
+use super::signal;
+use super::template::{Segment, Template};
+use super::units::Units;
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use crossterm::{
+    cursor, queue,
+    style::{self, Color, Print, PrintStyledContent, StyledContent},
+    terminal::{Clear, ClearType},
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{Stderr, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in the smoothed rate used for the ETA
+const SMOOTHING: f64 = 0.3;
+/// Span of the sliding window rate
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+/// How long nothing may flow before the display shows the transfer as stalled
+const STALL_AFTER: Duration = Duration::from_secs(5);
+/// How often the stats loop looks for stalls and signals while nothing arrives
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the other stages get to wind down after a signal
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+/// Number of characters inside the progress bar brackets
+const BAR_WIDTH: usize = 30;
+
+/// Messages sent from the read loop to the stats loop
+pub enum StatsMessage {
+    /// The read loop started on input number `index` (zero based) out of `count`
+    Input {
+        name: String,
+        size: Option<usize>,
+        index: usize,
+        count: usize,
+    },
+    /// Data read from the current input
+    Chunk(Chunk),
+    /// Bytes copied by the kernel without passing through the read loop
+    Count(usize),
+    /// Unreadable bytes replaced with zeros, which also arrive as a `Chunk`
+    Skipped(usize),
+    /// Bytes produced by the compression stage for the writers
+    Output(usize),
+    /// End of stream, the stats loop still counts `Output` until the channel closes
+    Done,
+}
+
+/// Counts newline-delimited records across chunk boundaries
+#[derive(Default)]
+pub struct LineCounter {
+    partial: bool,
+}
+
+impl LineCounter {
+    /// Number of records terminated within `chunk`
+    pub fn count(&mut self, chunk: &[u8]) -> usize {
+        if let Some(&last) = chunk.last() {
+            self.partial = last != b'\n';
+        }
+        chunk.iter().filter(|&&byte| byte == b'\n').count()
+    }
+
+    /// Returns 1 if the stream ended with an unterminated record
+    pub fn finish(&mut self) -> usize {
+        std::mem::take(&mut self.partial) as usize
+    }
+}
+
+/// Progress of the input that is currently being read
+struct CurrentInput {
+    name: String,
+    size: Option<usize>,
+    index: usize,
+    count: usize,
+    total: usize,
+}
+
+pub struct Stats {
+    total_bytes: usize,
+    /// Bytes actually seen flowing, the total less what `seek` set it to
+    transferred_bytes: usize,
+    /// Rate over the last display tick
+    bps: f64,
+    /// Exponentially weighted moving average of the tick rates
+    avg_bps: f64,
+    peak_bps: f64,
+    window_bps: f64,
+    /// Totals at the display ticks within the last `RATE_WINDOW`, oldest first
+    samples: VecDeque<(Instant, usize)>,
+    start: Instant,
+    last_instant: Instant,
+    tick_instant: Instant,
+    tick_bytes: usize,
+    delta: Duration,
+    period: Duration,
+    countdown: Duration,
+    ready: bool,
+    finished: bool,
+    stalled: bool,
+    paused: bool,
+    rate_limit: Option<usize>,
+    size: Option<usize>,
+    line_mode: bool,
+    input: Option<CurrentInput>,
+    checksum: Option<String>,
+    output_bytes: Option<usize>,
+    skipped_bytes: usize,
+    label: Option<String>,
+    units: Units,
+}
+
+impl Stats {
+    pub fn new(rate_limit: Option<usize>, size: Option<usize>, line_mode: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            total_bytes: 0,
+            transferred_bytes: 0,
+            bps: 0.0,
+            avg_bps: 0.0,
+            peak_bps: 0.0,
+            window_bps: 0.0,
+            samples: VecDeque::from(vec![(now, 0)]),
+            start: now,
+            last_instant: now,
+            tick_instant: now,
+            tick_bytes: 0,
+            delta: Duration::default(),
+            period: Duration::from_millis(1000),
+            countdown: Duration::default(),
+            ready: true,
+            finished: false,
+            stalled: false,
+            paused: false,
+            rate_limit,
+            size,
+            line_mode,
+            input: None,
+            checksum: None,
+            output_bytes: None,
+            skipped_bytes: 0,
+            label: None,
+            units: Units::default(),
+        }
+    }
+
+    /// Prefixes the display with `label`, telling several progress lines apart
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Formats sizes and rates with `units` instead of the default IEC units
+    pub fn with_units(mut self, units: Units) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Switches the per-input progress to a new input
+    pub fn start_input(&mut self, name: String, size: Option<usize>, index: usize, count: usize) {
+        self.input = Some(CurrentInput {
+            name,
+            size: if self.line_mode { None } else { size },
+            index,
+            count,
+            total: 0,
+        });
+    }
+
+    pub fn update(&mut self, num_read: usize) {
+        self.total_bytes += num_read;
+        self.transferred_bytes += num_read;
+        if let Some(input) = self.input.as_mut() {
+            input.total += num_read;
+        }
+        self.tick_bytes += num_read;
+        self.stalled = false;
+        let now = Instant::now();
+        self.delta = now - self.last_instant;
+        self.last_instant = now;
+        self.countdown = self.countdown.checked_sub(self.delta).unwrap_or_else(|| {
+            self.ready = true;
+            self.period
+        });
+        if self.ready {
+            self.smooth_rate(now);
+        }
+    }
+
+    /// Moves the total to `position` without counting it as transferred,
+    /// for sources whose position is read rather than summed up
+    pub fn seek(&mut self, position: usize) {
+        self.total_bytes = position;
+        // the sliding window restarts from the new position
+        self.samples.clear();
+        self.samples.push_back((self.tick_instant, position));
+        if let Some(input) = self.input.as_mut() {
+            input.total = position;
+        }
+    }
+
+    /// Returns whether a display tick is due and clears it
+    pub(crate) fn take_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.ready, false)
+    }
+
+    pub(crate) fn set_finished(&mut self) {
+        self.finished = true;
+        // a run shorter than a tick still gets its rates
+        let now = Instant::now();
+        if self.samples.len() == 1 && now > self.tick_instant {
+            self.take_tick(now);
+        }
+    }
+
+    /// Counts bytes leaving a compression stage, which makes the output total show up
+    pub fn update_output(&mut self, num: usize) {
+        *self.output_bytes.get_or_insert(0) += num;
+    }
+
+    /// Computes the rates from the data seen since the last display tick.
+    /// Ticks shorter than half a period are too noisy and keep accumulating instead.
+    fn smooth_rate(&mut self, now: Instant) {
+        if now - self.tick_instant >= self.period / 2 {
+            self.take_tick(now);
+        }
+    }
+
+    fn take_tick(&mut self, now: Instant) {
+        let elapsed = now - self.tick_instant;
+        let rate = self.tick_bytes as f64 / elapsed.as_secs_f64();
+        self.bps = rate;
+        self.peak_bps = self.peak_bps.max(rate);
+        self.avg_bps = if self.avg_bps == 0.0 {
+            rate
+        } else {
+            SMOOTHING * rate + (1.0 - SMOOTHING) * self.avg_bps
+        };
+        self.tick_bytes = 0;
+        self.tick_instant = now;
+
+        // keeps the newest sample at or before the start of the window
+        self.samples.push_back((now, self.total_bytes));
+        while self.samples.len() > 2 && now - self.samples[1].0 >= RATE_WINDOW {
+            self.samples.pop_front();
+        }
+        let (oldest, total) = self.samples[0];
+        self.window_bps =
+            self.total_bytes.saturating_sub(total) as f64 / (now - oldest).as_secs_f64();
+    }
+
+    /// Bytes (or lines in line mode) transferred so far
+    pub fn total(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Rate over the last display period, per second
+    pub fn rate(&self) -> f64 {
+        self.bps
+    }
+
+    /// Overall rate since the start, per second, not counting a starting `seek`.
+    /// 0 during the first display period of a running transfer, which is too
+    /// short to measure over.
+    pub fn average_rate(&self) -> f64 {
+        let elapsed = self.elapsed();
+        if elapsed >= self.period || (self.finished && elapsed > Duration::default()) {
+            self.transferred_bytes as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        }
+    }
+
+    /// Highest rate over a display period, per second. Never below the average,
+    /// which covers transfers shorter than a period.
+    pub fn peak_rate(&self) -> f64 {
+        self.peak_bps.max(self.average_rate())
+    }
+
+    /// Rate over the last ten seconds, per second
+    pub fn window_rate(&self) -> f64 {
+        self.window_bps
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Exponentially smoothed rate used for the ETA, per second
+    pub fn smoothed_rate(&self) -> f64 {
+        self.avg_bps
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Name of the input currently being read
+    pub fn input_name(&self) -> Option<&str> {
+        self.input.as_ref().map(|input| input.name.as_str())
+    }
+
+    /// Expected total, if known
+    pub fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    pub fn is_line_mode(&self) -> bool {
+        self.line_mode
+    }
+
+    /// Whether the stream has ended, which is the case for `Observer::finish`
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Whether nothing has flowed for a while, cleared by the next update
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
+    /// Whether the reader was paused by a remote command
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub(crate) fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            // the pause is no stall
+            self.last_instant = Instant::now();
+        }
+        self.paused = paused;
+    }
+
+    /// Time since data last came through
+    pub fn idle(&self) -> Duration {
+        self.last_instant.elapsed()
+    }
+
+    /// Hex digest of the transferred data, available once the stream has ended
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    /// Bytes produced by the compression stage, when there is one
+    pub fn output_total(&self) -> Option<usize> {
+        self.output_bytes
+    }
+
+    /// Bytes that could not be read and were written as zeros
+    pub fn skipped(&self) -> usize {
+        self.skipped_bytes
+    }
+
+    /// Output bytes per input byte, below 1 when compressing
+    pub fn ratio(&self) -> Option<f64> {
+        match self.output_bytes {
+            Some(output) if !self.line_mode && self.total_bytes > 0 => {
+                Some(output as f64 / self.total_bytes as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Completed share of the expected size in percent (0 - 100)
+    pub fn percentage(&self) -> Option<f64> {
+        self.size.map(|size| {
+            if size > 0 {
+                (self.total_bytes as f64 * 100.0 / size as f64).min(100.0)
+            } else {
+                100.0
+            }
+        })
+    }
+
+    /// Estimated seconds until the expected size is transferred, if known
+    pub fn eta(&self) -> Option<u64> {
+        let remaining = self.size?.saturating_sub(self.total_bytes);
+        if remaining == 0 {
+            Some(0)
+        } else if self.avg_bps > 0.0 {
+            Some((remaining as f64 / self.avg_bps).ceil() as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Renders a count of bytes or lines depending on the mode
+    fn fmt_amount(&self, count: usize) -> String {
+        if self.line_mode {
+            format!("{} lines", count)
+        } else {
+            self.units.size(count)
+        }
+    }
+
+    /// Renders a rate of bytes or lines per second depending on the mode
+    fn fmt_rate(&self, per_second: f64) -> String {
+        if self.line_mode {
+            format!("{:.0} lines/s", per_second)
+        } else {
+            self.units.rate(per_second)
+        }
+    }
+
+    /// The total so far as shown on the terminal, e.g. `1.5 MiB`
+    pub fn human_total(&self) -> String {
+        self.fmt_amount(self.total_bytes)
+    }
+
+    /// The current rate as shown on the terminal, e.g. `1.5 MiB/s`
+    pub fn human_rate(&self) -> String {
+        self.fmt_rate(self.bps)
+    }
+
+    /// One line summing up the transfer, for the end
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} in {}: average {}, peak {}, last 10s {}, smoothed {}",
+            self.fmt_amount(self.total_bytes),
+            self.elapsed().as_secs().as_time(),
+            self.fmt_rate(self.average_rate()),
+            self.fmt_rate(self.peak_rate()),
+            self.fmt_rate(self.window_bps),
+            self.fmt_rate(self.avg_bps)
+        );
+        if self.skipped_bytes > 0 {
+            summary += &format!(
+                ", {} unreadable written as zeros",
+                self.units.size(self.skipped_bytes)
+            );
+        }
+        summary
+    }
+
+    /// Renders one template segment, `None` when there is nothing to show
+    fn segment(&self, segment: &Segment) -> Option<StyledContent<String>> {
+        let content = match segment {
+            Segment::Literal(text) => style::style(text.clone()),
+            Segment::Name => style::style(format!("{}:", self.label.as_ref()?)),
+            Segment::Input => {
+                let input = self.input.as_ref().filter(|input| input.count > 1)?;
+                let progress = match input.size {
+                    Some(size) if size > 0 => format!(
+                        " of {}, {:.0}%",
+                        self.fmt_amount(size),
+                        input.total as f64 * 100.0 / size as f64
+                    ),
+                    _ => String::new(),
+                };
+                let text = format!(
+                    "{} {}/{} [{}{}]",
+                    input.name,
+                    input.index + 1,
+                    input.count,
+                    self.fmt_amount(input.total),
+                    progress
+                );
+                style::style(text).with(Color::Magenta)
+            }
+            Segment::Bytes => {
+                let mut text = match (self.output_bytes, self.ratio()) {
+                    (Some(output), Some(ratio)) => format!(
+                        "{} -> {} ({:.2}x)",
+                        self.fmt_amount(self.total_bytes),
+                        self.units.size(output),
+                        ratio
+                    ),
+                    (Some(output), None) => format!(
+                        "{} -> {}",
+                        self.fmt_amount(self.total_bytes),
+                        self.units.size(output)
+                    ),
+                    _ => self.fmt_amount(self.total_bytes),
+                };
+                if self.skipped_bytes > 0 {
+                    text += &format!(" ({} skipped)", self.units.size(self.skipped_bytes));
+                }
+                style::style(text).with(Color::Red)
+            }
+            Segment::Timer => {
+                style::style(self.start.elapsed().as_secs().as_time()).with(Color::Green)
+            }
+            Segment::Rate if self.paused => {
+                style::style(String::from("[PAUSED]")).with(Color::Yellow)
+            }
+            Segment::Rate if self.stalled => {
+                style::style(String::from("[STALLED]")).with(Color::Red)
+            }
+            Segment::Rate => {
+                let text = match self.rate_limit {
+                    Some(limit) => format!(
+                        "[{} / limit {}]",
+                        self.fmt_rate(self.bps),
+                        self.units.rate(limit as f64)
+                    ),
+                    None => format!("[{}]", self.fmt_rate(self.bps)),
+                };
+                style::style(text).with(Color::Blue)
+            }
+            Segment::AverageRate => {
+                style::style(format!("[{}]", self.fmt_rate(self.average_rate()))).with(Color::Blue)
+            }
+            Segment::Progress => {
+                let fraction = self.percentage()? / 100.0;
+                let text = format!(
+                    "{} {:.0}%",
+                    progress_bar(fraction, BAR_WIDTH),
+                    fraction * 100.0
+                );
+                style::style(text).with(Color::Yellow)
+            }
+            Segment::Left => {
+                let left = self.size?.saturating_sub(self.total_bytes);
+                style::style(format!("{} left", self.fmt_amount(left))).with(Color::Yellow)
+            }
+            Segment::Eta => {
+                self.size?;
+                let eta = match self.eta() {
+                    Some(secs) => secs.as_time(),
+                    None => String::from("-:--:--"),
+                };
+                style::style(format!("ETA {}", eta)).with(Color::Yellow)
+            }
+        };
+        Some(content)
+    }
+
+    /// Renders the stats as a single, continuously rewritten line laid out by `template`
+    pub fn print(&self, stderr: &mut Stderr, template: &Template) {
+        let _ = queue!(
+            stderr,
+            cursor::MoveToColumn(0),
+            Clear(ClearType::CurrentLine)
+        );
+        // an empty segment takes the whitespace after it along
+        let mut skip_space = false;
+        for segment in template.segments() {
+            match (segment, self.segment(segment)) {
+                (Segment::Literal(text), _) if skip_space => {
+                    let _ = queue!(stderr, Print(text.trim_start()));
+                    skip_space = false;
+                }
+                (_, Some(content)) => {
+                    let _ = queue!(stderr, PrintStyledContent(content));
+                    skip_space = false;
+                }
+                (_, None) => skip_space = true,
+            }
+        }
+        let _ = stderr.flush();
+    }
+}
+
+/// Draws a `[====>    ]` bar filled to `fraction` (0.0 - 1.0)
+fn progress_bar(fraction: f64, width: usize) -> String {
+    let filled = (fraction * width as f64).round() as usize;
+    let mut bar = String::with_capacity(width + 2);
+    bar.push('[');
+    for i in 0..width {
+        bar.push(match i {
+            i if i + 1 < filled => '=',
+            i if i + 1 == filled && filled == width => '=',
+            i if i + 1 == filled => '>',
+            _ => ' ',
+        });
+    }
+    bar.push(']');
+    bar
+}
+
+/// The TimeOutput trait adds a `.as_time()` method to `u64`
+///
+/// # Example
+/// Here us an example.
+/// ```rust
+/// use pipeviewer::stats::TimeOutput;
+/// assert_eq!(12345_u64.as_time(), String::from("3:25:45"));
+/// ```
+pub trait TimeOutput {
+    fn as_time(&self) -> String;
+}
+
+impl TimeOutput for u64 {
+    /// Renders the u64 into a time string
+    fn as_time(&self) -> String {
+        let (hours, left) = (*self / 3600, *self % 3600);
+        let (minutes, seconds) = (left / 60, left % 60);
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} [{}]",
+            self.human_total(),
+            self.start.elapsed().as_secs().as_time(),
+            self.human_rate()
+        )
+    }
+}
+
+/// Shows the effect of remote commands, the read loop applies them as well
+fn apply_commands(
+    timer: &mut Stats,
+    observers: &mut [Box<dyn Observer>],
+    control: &Receiver<Command>,
+) {
+    let mut changed = false;
+    for command in control.try_iter() {
+        match command {
+            Command::RateLimit(rate) => timer.rate_limit = rate,
+            Command::Format(template) => {
+                for observer in observers.iter_mut() {
+                    observer.set_template(&template);
+                }
+            }
+            Command::Pause => timer.set_paused(true),
+            Command::Resume => timer.set_paused(false),
+        }
+        changed = true;
+    }
+    if changed {
+        for observer in observers.iter_mut() {
+            observer.update(timer);
+        }
+    }
+}
+
+/// Tracks the progress reported by the read loop in `timer` and hands it to the `observers`.
+/// The `checksum` digest is written to `checksum_file` as well when one is given.
+///
+/// The display keeps ticking while nothing flows, showing the transfer as stalled,
+/// and after `stall_timeout` without data the loop gives up with `PipelineError::Stalled`.
+/// After a signal it gives the other stages a moment to finish, then gives up on them
+/// with `PipelineError::Interrupted`.
+pub fn stats_loop(
+    mut timer: Stats,
+    checksum: Option<Algorithm>,
+    checksum_file: Option<PathBuf>,
+    stall_timeout: Option<Duration>,
+    control: Option<Receiver<Command>>,
+    mut observers: Vec<Box<dyn Observer>>,
+    stats_rx: Receiver<StatsMessage>,
+) -> Result<()> {
+    let line_mode = timer.line_mode;
+    let mut lines = LineCounter::default();
+    let mut hasher = checksum.map(Hasher::new);
+    let mut result = Ok(());
+    let mut done = false;
+    let stall_after = stall_timeout.map_or(STALL_AFTER, |timeout| timeout.min(STALL_AFTER));
+    let mut stall_drawn = Instant::now();
+    let mut interrupted: Option<Instant> = None;
+    loop {
+        if let Some(control) = &control {
+            apply_commands(&mut timer, &mut observers, control);
+        }
+        let num = match stats_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(StatsMessage::Input {
+                name,
+                size,
+                index,
+                count,
+            }) => {
+                timer.start_input(name, size, index, count);
+                continue;
+            }
+            Ok(StatsMessage::Chunk(chunk)) => {
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&chunk);
+                }
+                if line_mode {
+                    lines.count(&chunk)
+                } else {
+                    chunk.len()
+                }
+            }
+            Ok(StatsMessage::Count(num)) => num,
+            Ok(StatsMessage::Skipped(num)) => {
+                timer.skipped_bytes += num;
+                continue;
+            }
+            Ok(StatsMessage::Output(num)) => {
+                timer.update_output(num);
+                continue;
+            }
+            // a compression stage may still be flushing its output
+            Ok(StatsMessage::Done) => {
+                done = true;
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // the reader stops by itself, unless it is stuck in a read
+                if let Some(signal) = signal::received() {
+                    if interrupted.get_or_insert_with(Instant::now).elapsed() >= SHUTDOWN_GRACE {
+                        result = Err(PipelineError::Interrupted(signal));
+                        break;
+                    }
+                    continue;
+                }
+                let idle = timer.idle();
+                if done || timer.paused || idle < stall_after {
+                    continue;
+                }
+                if let Some(timeout) = stall_timeout.filter(|&timeout| idle >= timeout) {
+                    timer.stalled = true;
+                    result = Err(PipelineError::Stalled(timeout));
+                    break;
+                }
+                // keeps the timer going once per period
+                if !timer.stalled || stall_drawn.elapsed() >= timer.period {
+                    timer.stalled = true;
+                    stall_drawn = Instant::now();
+                    for observer in observers.iter_mut() {
+                        observer.update(&timer);
+                    }
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) if done => break,
+            Err(RecvTimeoutError::Disconnected) => {
+                result = Err(PipelineError::Disconnected("read"));
+                break;
+            }
+        };
+        timer.update(num);
+        if timer.take_ready() {
+            for observer in observers.iter_mut() {
+                observer.update(&timer);
+            }
+        }
+    }
+    if line_mode && lines.finish() > 0 {
+        timer.update(1);
+    }
+    // a digest of a partial stream is not worth reporting
+    if let (Some(hasher), Ok(())) = (hasher, &result) {
+        let digest = hasher.finalize();
+        if let Some(path) = checksum_file {
+            // same layout as sha256sum and friends, `-` when not a single named file
+            let name = match &timer.input {
+                Some(input) if input.count == 1 && input.name != "stdin" => input.name.as_str(),
+                _ => "-",
+            };
+            let line = format!("{}  {}\n", digest, name);
+            if let Err(source) = fs::write(&path, line) {
+                result = Err(PipelineError::Write {
+                    name: path.display().to_string(),
+                    source,
+                });
+            }
+        }
+        timer.checksum = Some(digest);
+    }
+    timer.set_finished();
+    for observer in observers.iter_mut() {
+        observer.finish(&timer);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{progress_bar, LineCounter, Stats, TimeOutput};
+    use crate::template::Segment;
+    use std::time::Duration;
+
+    #[test]
+    fn as_time_format() {
+        let pairs = vec![
+            (5_u64, "0:00:05"),
+            (60_u64, "0:01:00"),
+            (3600_u64, "1:00:00"),
+            (85_u64, "0:01:25"),
+            (12345_u64, "3:25:45"),
+            (3599_u64, "0:59:59"),
+        ];
+        for (input, output) in pairs {
+            assert_eq!(input.as_time().as_str(), output);
+        }
+    }
+
+    #[test]
+    fn progress_bar_fill() {
+        assert_eq!(progress_bar(0.0, 4), "[    ]");
+        assert_eq!(progress_bar(0.5, 4), "[=>  ]");
+        assert_eq!(progress_bar(1.0, 4), "[====]");
+    }
+
+    #[test]
+    fn line_counter_straddling_chunks() {
+        let mut lines = LineCounter::default();
+        let chunks: Vec<&[u8]> = vec![b"ab", b"c\nde", b"f\n", b"\n\ng"];
+        let counts: Vec<usize> = chunks.iter().map(|chunk| lines.count(chunk)).collect();
+        assert_eq!(counts, vec![0, 1, 1, 2]);
+        assert_eq!(lines.finish(), 1);
+        assert_eq!(lines.finish(), 0);
+    }
+
+    #[test]
+    fn line_counter_terminated_stream() {
+        let mut lines = LineCounter::default();
+        assert_eq!(lines.count(b"one\ntwo\n"), 2);
+        assert_eq!(lines.finish(), 0);
+    }
+
+    #[test]
+    fn rates_over_ticks() {
+        let mut stats = Stats::new(None, None, false);
+        let start = stats.tick_instant;
+        // 1000 bytes/s, then 3000 bytes/s, one second each, then nothing for 10 seconds
+        for (second, bytes) in &[(1, 1000), (2, 3000), (12, 0)] {
+            stats.total_bytes += bytes;
+            stats.tick_bytes += bytes;
+            stats.smooth_rate(start + Duration::from_secs(*second));
+            if *second == 2 {
+                assert_eq!(stats.rate(), 3000.0);
+                assert_eq!(stats.window_rate(), 2000.0);
+            }
+        }
+        assert_eq!(stats.rate(), 0.0);
+        assert_eq!(stats.peak_bps, 3000.0);
+        assert_eq!(stats.window_rate(), 0.0);
+    }
+
+    #[test]
+    fn seek_is_not_transferred() {
+        let mut stats = Stats::new(None, None, false);
+        // attaching 40 MB into a file, then reading 1000 bytes
+        stats.seek(40_000_000);
+        stats.update(1000);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(stats.total(), 40_001_000);
+        assert!(stats.average_rate() <= 100_000.0);
+        assert!(stats.peak_rate() <= 100_000.0);
+    }
+
+    #[test]
+    fn input_segment_follows_the_current_input() {
+        let mut stats = Stats::new(None, Some(3048), false);
+        let input = |stats: &Stats| {
+            stats
+                .segment(&Segment::Input)
+                .map(|segment| segment.content().clone())
+        };
+        stats.start_input(String::from("a"), Some(1000), 0, 2);
+        stats.update(250);
+        assert_eq!(
+            input(&stats).as_deref(),
+            Some("a 1/2 [250 B of 1000 B, 25%]")
+        );
+        stats.update(750);
+        stats.start_input(String::from("b"), Some(2048), 1, 2);
+        stats.update(1024);
+        assert_eq!(
+            input(&stats).as_deref(),
+            Some("b 2/2 [1.0 KiB of 2.0 KiB, 50%]")
+        );
+        assert_eq!(stats.total(), 2024);
+        // a single input needs no segment of its own
+        stats.start_input(String::from("c"), None, 0, 1);
+        assert_eq!(input(&stats), None);
+    }
+
+    #[test]
+    fn no_average_before_a_period() {
+        let mut stats = Stats::new(None, None, false);
+        stats.update(100_000);
+        assert_eq!(stats.average_rate(), 0.0);
+        assert_eq!(stats.peak_rate(), 0.0);
+        // a transfer shorter than a period still gets its rates at the end
+        std::thread::sleep(Duration::from_millis(10));
+        stats.set_finished();
+        assert!(stats.average_rate() > 0.0);
+        assert!(stats.peak_rate() >= stats.average_rate());
+    }
+}
@@ -0,0 +1,165 @@
+//! Display templates such as `"%N %b %t %r %p"`, parsed once and rendered by `Stats::print`.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// One part of a template
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    /// `%N`, the label given with `--name`
+    Name,
+    /// `%I`, the current input when there are several
+    Input,
+    /// `%b`, bytes (or lines) so far, and the output with the ratio after a transform
+    Bytes,
+    /// `%t`, elapsed time
+    Timer,
+    /// `%r`, current rate with the rate limit
+    Rate,
+    /// `%a`, average rate since the start
+    AverageRate,
+    /// `%p`, progress bar and percentage
+    Progress,
+    /// `%l`, amount left
+    Left,
+    /// `%e`, estimated time left
+    Eta,
+}
+
+impl Segment {
+    fn from_specifier(specifier: char) -> Option<Self> {
+        Some(match specifier {
+            'N' => Segment::Name,
+            'I' => Segment::Input,
+            'b' => Segment::Bytes,
+            't' => Segment::Timer,
+            'r' => Segment::Rate,
+            'a' => Segment::AverageRate,
+            'p' => Segment::Progress,
+            'l' => Segment::Left,
+            'e' => Segment::Eta,
+            _ => return None,
+        })
+    }
+
+    fn specifier(&self) -> Option<char> {
+        Some(match self {
+            Segment::Literal(_) => return None,
+            Segment::Name => 'N',
+            Segment::Input => 'I',
+            Segment::Bytes => 'b',
+            Segment::Timer => 't',
+            Segment::Rate => 'r',
+            Segment::AverageRate => 'a',
+            Segment::Progress => 'p',
+            Segment::Left => 'l',
+            Segment::Eta => 'e',
+        })
+    }
+}
+
+/// A parsed display template.
+///
+/// A segment with nothing to show, like `%N` without a name or `%p` without a size,
+/// also drops the whitespace after it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+}
+
+/// The layout used without `--format`
+impl Default for Template {
+    fn default() -> Self {
+        "%N %I %b %t %r %p %l %e".parse().unwrap()
+    }
+}
+
+/// Writes the template back in the `--format` syntax
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            if let Segment::Literal(text) = segment {
+                f.write_str(&text.replace('%', "%%"))?;
+            } else if let Some(specifier) = segment.specifier() {
+                write!(f, "%{}", specifier)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for Template {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let segment = match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some(specifier) => Segment::from_specifier(specifier).ok_or_else(|| {
+                    ParseError(format!("unknown format specifier `%{}`", specifier))
+                })?,
+                None => return Err(ParseError(String::from("format ends in a lone `%`"))),
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Segment, Template};
+
+    #[test]
+    fn parses_specifiers_and_literals() {
+        let template: Template = "%N: %b at %r, 100%%".parse().unwrap();
+        assert_eq!(
+            template.segments(),
+            &[
+                Segment::Name,
+                Segment::Literal(String::from(": ")),
+                Segment::Bytes,
+                Segment::Literal(String::from(" at ")),
+                Segment::Rate,
+                Segment::Literal(String::from(", 100%")),
+            ]
+        );
+        assert_eq!(template.to_string(), "%N: %b at %r, 100%%");
+        assert!("%b %x".parse::<Template>().is_err());
+        assert!("%b %".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,402 @@
+//! Filters running on their own threads between the read loop and the writers.
+//!
+//! A [`Transform`] gets the chunks in order and appends its output to a `Vec`, which
+//! [`transform_loop`] hands on in pool buffers. Pipelines chain any number of them.
+
+use super::error::{PipelineError, Result};
+use super::pool::BufferPool;
+use super::stats::StatsMessage;
+use super::write::WriteMessage;
+use crossbeam::channel::{Receiver, Sender};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// Encoded characters per line, the same as coreutils `base64`
+const BASE64_WRAP: usize = 76;
+
+pub trait Transform: Send {
+    /// Names the transform in error messages
+    fn name(&self) -> String;
+
+    /// Transforms the next piece of the stream, appending the result to `output`
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Appends whatever is still held back once the stream has ended
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transform for Box<dyn Transform> {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        (**self).update(data, output)
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        (**self).finish(output)
+    }
+}
+
+/// Creates one of the built-in transforms from its command line name:
+/// `upper`, `hexdump`, `base64`, `base64-decode` or `tr:FROM:TO`.
+pub fn from_spec(spec: &str) -> std::result::Result<Box<dyn Transform>, String> {
+    match spec {
+        "upper" => Ok(Box::new(Uppercase)),
+        "hexdump" => Ok(Box::new(HexDump::default())),
+        "base64" => Ok(Box::new(Base64Encode::default())),
+        "base64-decode" => Ok(Box::new(Base64Decode::default())),
+        _ => match spec
+            .strip_prefix("tr:")
+            .and_then(|rest| rest.split_once(':'))
+        {
+            Some((from, to)) => Substitute::new(from.as_bytes(), to.as_bytes())
+                .map(|t| Box::new(t) as Box<dyn Transform>),
+            None => Err(format!("unknown transform `{}`", spec)),
+        },
+    }
+}
+
+/// Runs `transform` over the chunks from `rx` and passes the output on to `tx`.
+/// The last transform before the writers reports the bytes it produces on `stats_tx`.
+pub fn transform_loop(
+    mut transform: Box<dyn Transform>,
+    pool: BufferPool,
+    rx: Receiver<WriteMessage>,
+    stats_tx: Option<Sender<StatsMessage>>,
+    tx: Sender<WriteMessage>,
+) -> Result<()> {
+    if let Some(stats_tx) = &stats_tx {
+        // shows the output count before the transform produced anything
+        let _ = stats_tx.send(StatsMessage::Output(0));
+    }
+    let send = |data: &[u8]| -> bool {
+        for piece in data.chunks(pool.buffer_size()) {
+            let mut buffer = pool.take();
+            buffer.space()[..piece.len()].copy_from_slice(piece);
+            buffer.set_len(piece.len());
+            if let Some(stats_tx) = &stats_tx {
+                let _ = stats_tx.send(StatsMessage::Output(piece.len()));
+            }
+            if tx.send(WriteMessage::Chunk(Arc::new(buffer))).is_err() {
+                return false;
+            }
+        }
+        true
+    };
+
+    let mut output = Vec::new();
+    loop {
+        let chunk = match rx.recv() {
+            Ok(WriteMessage::Chunk(chunk)) => chunk,
+            Ok(WriteMessage::Done) => break,
+            Err(_) => return Err(PipelineError::Disconnected("read")),
+        };
+        output.clear();
+        if let Err(source) = transform.update(&chunk, &mut output) {
+            return Err(PipelineError::Transform {
+                name: transform.name(),
+                source,
+            });
+        }
+        if !send(&output) {
+            // the next stage is gone, its error says why
+            return Ok(());
+        }
+    }
+    output.clear();
+    if let Err(source) = transform.finish(&mut output) {
+        return Err(PipelineError::Transform {
+            name: transform.name(),
+            source,
+        });
+    }
+    if send(&output) {
+        let _ = tx.send(WriteMessage::Done);
+    }
+    Ok(())
+}
+
+/// Uppercases ASCII letters
+pub struct Uppercase;
+
+impl Transform for Uppercase {
+    fn name(&self) -> String {
+        String::from("upper")
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        output.extend(data.iter().map(u8::to_ascii_uppercase));
+        Ok(())
+    }
+}
+
+/// Replaces every byte of `from` with the byte at the same position in `to`, like `tr`
+pub struct Substitute {
+    table: [u8; 256],
+}
+
+impl Substitute {
+    pub fn new(from: &[u8], to: &[u8]) -> std::result::Result<Self, String> {
+        if from.len() != to.len() {
+            return Err(String::from(
+                "tr needs as many bytes to replace as replacements",
+            ));
+        }
+        let mut table = [0; 256];
+        for (byte, slot) in table.iter_mut().enumerate() {
+            *slot = byte as u8;
+        }
+        for (&from, &to) in from.iter().zip(to) {
+            table[from as usize] = to;
+        }
+        Ok(Self { table })
+    }
+}
+
+impl Transform for Substitute {
+    fn name(&self) -> String {
+        String::from("tr")
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        output.extend(data.iter().map(|&byte| self.table[byte as usize]));
+        Ok(())
+    }
+}
+
+/// Canonical hex and ASCII lines like `hexdump -C`, without squeezing repeated lines
+#[derive(Default)]
+pub struct HexDump {
+    offset: usize,
+    line: Vec<u8>,
+}
+
+impl HexDump {
+    fn dump_line(&mut self, output: &mut Vec<u8>) {
+        let mut text = format!("{:08x}  ", self.offset);
+        for i in 0..16 {
+            match self.line.get(i) {
+                Some(byte) => text.push_str(&format!("{:02x} ", byte)),
+                None => text.push_str("   "),
+            }
+            if i == 7 {
+                text.push(' ');
+            }
+        }
+        text.push_str(" |");
+        for &byte in &self.line {
+            text.push(if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+        text.push_str("|\n");
+        output.extend_from_slice(text.as_bytes());
+        self.offset += self.line.len();
+        self.line.clear();
+    }
+}
+
+impl Transform for HexDump {
+    fn name(&self) -> String {
+        String::from("hexdump")
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in data {
+            self.line.push(byte);
+            if self.line.len() == 16 {
+                self.dump_line(output);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.dump_line(output);
+        }
+        if self.offset > 0 {
+            output.extend_from_slice(format!("{:08x}\n", self.offset).as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Standard base64 with padding, wrapped at 76 characters
+#[derive(Default)]
+pub struct Base64Encode {
+    pending: Vec<u8>,
+    column: usize,
+}
+
+impl Base64Encode {
+    fn push(&mut self, output: &mut Vec<u8>, character: u8) {
+        output.push(character);
+        self.column += 1;
+        if self.column == BASE64_WRAP {
+            output.push(b'\n');
+            self.column = 0;
+        }
+    }
+
+    fn encode(&mut self, group: &[u8], output: &mut Vec<u8>) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            let character = if i <= group.len() {
+                BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize]
+            } else {
+                b'='
+            };
+            self.push(output, character);
+        }
+    }
+}
+
+impl Transform for Base64Encode {
+    fn name(&self) -> String {
+        String::from("base64")
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        self.pending.extend_from_slice(data);
+        let whole = self.pending.len() / 3 * 3;
+        let pending = std::mem::take(&mut self.pending);
+        for group in pending[..whole].chunks(3) {
+            self.encode(group, output);
+        }
+        self.pending = pending[whole..].to_vec();
+        Ok(())
+    }
+
+    fn finish(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.encode(&pending, output);
+        }
+        if self.column > 0 {
+            output.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
+/// Decodes standard base64, skipping whitespace
+#[derive(Default)]
+pub struct Base64Decode {
+    group: Vec<u8>,
+    padded: bool,
+}
+
+impl Base64Decode {
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(ErrorKind::InvalidData, message.to_string())
+    }
+
+    fn decode(&mut self, output: &mut Vec<u8>) -> io::Result<()> {
+        let padding = self.group.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || self.group[..4 - padding].contains(&b'=') {
+            return Err(Self::invalid("misplaced base64 padding"));
+        }
+        let mut bits = 0u32;
+        for &character in &self.group[..4 - padding] {
+            let value = BASE64_ALPHABET
+                .iter()
+                .position(|&c| c == character)
+                .ok_or_else(|| Self::invalid("invalid base64 character"))?;
+            bits = bits << 6 | value as u32;
+        }
+        bits <<= 6 * padding;
+        output.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+        self.padded = padding > 0;
+        self.group.clear();
+        Ok(())
+    }
+}
+
+impl Transform for Base64Decode {
+    fn name(&self) -> String {
+        String::from("base64-decode")
+    }
+
+    fn update(&mut self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        for &character in data {
+            if character.is_ascii_whitespace() {
+                continue;
+            }
+            if self.padded {
+                return Err(Self::invalid("base64 data after padding"));
+            }
+            self.group.push(character);
+            if self.group.len() == 4 {
+                self.decode(output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _output: &mut Vec<u8>) -> io::Result<()> {
+        if self.group.is_empty() {
+            Ok(())
+        } else {
+            Err(Self::invalid("truncated base64 data"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_spec, Transform};
+
+    fn run(mut transform: Box<dyn Transform>, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        // odd piece sizes, so the transforms see their state split across chunks
+        for piece in input.chunks(7) {
+            transform.update(piece, &mut output).unwrap();
+        }
+        transform.finish(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn simple_filters() {
+        assert_eq!(
+            run(from_spec("upper").unwrap(), b"Hello, w0rld"),
+            b"HELLO, W0RLD"
+        );
+        assert_eq!(run(from_spec("tr:lo:01").unwrap(), b"hello"), b"he001");
+        assert!(from_spec("tr:ab:c").is_err());
+        assert!(from_spec("rot13").is_err());
+    }
+
+    #[test]
+    fn hexdump_matches_canonical_format() {
+        let dump = run(from_spec("hexdump").unwrap(), b"0123456789abcdefhello\n");
+        assert_eq!(
+            String::from_utf8(dump).unwrap(),
+            "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+             00000010  68 65 6c 6c 6f 0a                                 |hello.|\n\
+             00000016\n"
+        );
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for input in &[&b""[..], b"f", b"fo", b"foo", b"foob", &[0xff; 100]] {
+            let encoded = run(from_spec("base64").unwrap(), input);
+            assert_eq!(&run(from_spec("base64-decode").unwrap(), &encoded), input);
+        }
+        assert_eq!(run(from_spec("base64").unwrap(), b"foob"), b"Zm9vYg==\n");
+        let mut decode = from_spec("base64-decode").unwrap();
+        assert!(decode.update(b"Zm9!", &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Parsing and formatting of human friendly sizes such as `5M` or `1.5GiB`, and durations.
+
+use std::time::Duration;
+
+const IEC_PREFIXES: [&str; 6] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+const SI_PREFIXES: [&str; 6] = ["k", "M", "G", "T", "P", "E"];
+
+/// Parses a size with an optional binary suffix (`K`, `M`, `G`, `T`) into bytes.
+///
+/// The suffix is case insensitive and may be followed by `B` or `iB`,
+/// so `5M`, `5mb` and `5MiB` all mean 5 * 1024 * 1024 bytes.
+pub fn parse_size(input: &str) -> Result<usize, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, suffix) = input.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", input))?;
+    let suffix = suffix.trim().to_ascii_lowercase();
+    let suffix = suffix
+        .strip_suffix("ib")
+        .or_else(|| suffix.strip_suffix('b'))
+        .unwrap_or(&suffix);
+    let multiplier: usize = match suffix {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => 1024 * 1024 * 1024,
+        "t" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size suffix in '{}'", input)),
+    };
+    Ok((number * multiplier as f64) as usize)
+}
+
+/// Parses a duration such as `30s`, `1.5m`, `2h` or `500ms`, plain numbers being seconds
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, suffix) = input.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", input))?;
+    let seconds = match suffix.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration suffix in '{}'", input)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration '{}' is too long", input))
+}
+
+/// Whether sizes are shown in powers of 1024 or 1000
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitSystem {
+    /// `KiB`, `MiB`, ... (powers of 1024)
+    Iec,
+    /// `kB`, `MB`, ... (powers of 1000)
+    Si,
+}
+
+/// Formats byte counts and rates for display, e.g. `1.5 MiB` or `12.6 Mbit/s`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Units {
+    system: UnitSystem,
+    precision: usize,
+    bits: bool,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Self {
+            system: UnitSystem::Iec,
+            precision: 1,
+            bits: false,
+        }
+    }
+}
+
+impl Units {
+    pub fn system(mut self, system: UnitSystem) -> Self {
+        self.system = system;
+        self
+    }
+
+    /// Number of decimals shown once a prefix is used
+    pub fn precision(mut self, precision: usize) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Shows rates in bits rather than bytes per second
+    pub fn bits(mut self, bits: bool) -> Self {
+        self.bits = bits;
+        self
+    }
+
+    /// Scales `value` down to the largest prefix it reaches
+    fn scale(&self, value: f64, unit: &str) -> String {
+        let (base, prefixes) = match self.system {
+            UnitSystem::Iec => (1024.0, &IEC_PREFIXES),
+            UnitSystem::Si => (1000.0, &SI_PREFIXES),
+        };
+        if value < base {
+            return format!("{:.0} {}", value, unit);
+        }
+        let mut scaled = value / base;
+        let mut prefix = 0;
+        while scaled >= base && prefix + 1 < prefixes.len() {
+            scaled /= base;
+            prefix += 1;
+        }
+        format!("{:.*} {}{}", self.precision, scaled, prefixes[prefix], unit)
+    }
+
+    /// Renders a number of bytes, e.g. `512 B` or `1.5 MiB`
+    pub fn size(&self, bytes: usize) -> String {
+        self.scale(bytes as f64, "B")
+    }
+
+    /// Renders a number of bytes per second, e.g. `1.5 MiB/s` or `12.6 Mibit/s`
+    pub fn rate(&self, bytes_per_second: f64) -> String {
+        if self.bits {
+            self.scale(bytes_per_second * 8.0, "bit/s")
+        } else {
+            self.scale(bytes_per_second, "B/s")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, parse_size, UnitSystem, Units};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_size_suffixes() {
+        let pairs = vec![
+            ("512", 512),
+            ("1k", 1024),
+            ("5M", 5 * 1024 * 1024),
+            ("5MB", 5 * 1024 * 1024),
+            ("5MiB", 5 * 1024 * 1024),
+            ("1.5G", 3 * 512 * 1024 * 1024),
+            ("2T", 2 * 1024 * 1024 * 1024 * 1024),
+        ];
+        for (input, output) in pairs {
+            assert_eq!(parse_size(input), Ok(output));
+        }
+    }
+
+    #[test]
+    fn parse_size_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("5X").is_err());
+    }
+
+    #[test]
+    fn parse_duration_suffixes() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("99999999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn formats_sizes_and_rates() {
+        let iec = Units::default();
+        assert_eq!(iec.size(512), "512 B");
+        assert_eq!(iec.size(1024), "1.0 KiB");
+        assert_eq!(iec.size(3 * 512 * 1024 * 1024), "1.5 GiB");
+        assert_eq!(iec.rate(1536.0), "1.5 KiB/s");
+
+        let si = Units::default().system(UnitSystem::Si).precision(2);
+        assert_eq!(si.size(999), "999 B");
+        assert_eq!(si.size(1_234_567), "1.23 MB");
+        assert_eq!(si.bits(true).rate(1_500_000.0), "12.00 Mbit/s");
+        assert_eq!(iec.bits(true).rate(128.0), "1.0 Kibit/s");
+    }
+}
@@ -0,0 +1,237 @@
+//! Progress of the files another process has open, polled from `/proc` like `pv -d`.
+
+use super::error::{PipelineError, Result};
+use super::observer::Observer;
+use super::signal;
+use super::stats::Stats;
+use super::units::Units;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+
+/// The parts of `/proc/<pid>/fdinfo/<fd>` that matter here
+#[derive(Debug, PartialEq)]
+struct FdInfo {
+    pos: usize,
+    write_only: bool,
+}
+
+fn parse_fdinfo(text: &str) -> Option<FdInfo> {
+    let mut pos = None;
+    let mut flags = None;
+    for line in text.lines() {
+        if let Some(value) = line.strip_prefix("pos:") {
+            pos = value.trim().parse().ok();
+        } else if let Some(value) = line.strip_prefix("flags:") {
+            flags = u32::from_str_radix(value.trim(), 8).ok();
+        }
+    }
+    Some(FdInfo {
+        pos: pos?,
+        write_only: flags? & O_ACCMODE == O_WRONLY,
+    })
+}
+
+/// A descriptor being watched and the file it pointed to when first seen
+struct Watched {
+    fd: u32,
+    path: PathBuf,
+    pos: usize,
+    stats: Stats,
+}
+
+/// Lists `fd`, or every descriptor of the process that is a regular file
+fn open_files(proc_dir: &Path, fd: Option<u32>) -> io::Result<Vec<(u32, PathBuf)>> {
+    let fd_dir = proc_dir.join("fd");
+    if let Some(fd) = fd {
+        return Ok(vec![(fd, fs::read_link(fd_dir.join(fd.to_string()))?)]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&fd_dir)? {
+        let entry = entry?;
+        let fd = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(fd) => fd,
+            None => continue,
+        };
+        // the descriptor may be closed in the meantime
+        match (fs::read_link(entry.path()), fs::metadata(entry.path())) {
+            (Ok(path), Ok(meta)) if meta.is_file() => files.push((fd, path)),
+            _ => (),
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Starts watching `fd`, the size being the file size unless it is only written to
+fn watch(proc_dir: &Path, fd: u32, path: PathBuf, units: Units) -> io::Result<Watched> {
+    let text = fs::read_to_string(proc_dir.join("fdinfo").join(fd.to_string()))?;
+    let info = parse_fdinfo(&text)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "unexpected fdinfo format"))?;
+    let meta = fs::metadata(proc_dir.join("fd").join(fd.to_string()))?;
+    let size = if meta.is_file() && !info.write_only {
+        Some(meta.len() as usize)
+    } else {
+        None
+    };
+    let name = path.display().to_string();
+    let mut stats = Stats::new(None, size, false)
+        .with_label(format!("{}:{}", fd, name))
+        .with_units(units);
+    stats.start_input(name, size, 0, 1);
+    stats.seek(info.pos);
+    Ok(Watched {
+        fd,
+        path,
+        pos: info.pos,
+        stats,
+    })
+}
+
+/// Shows the progress of descriptor `fd` of process `pid`, or of all the regular files
+/// it has open, until they are closed, the process exits or a signal arrives.
+pub fn watch_loop(
+    pid: u32,
+    fd: Option<u32>,
+    units: Units,
+    mut observers: Vec<Box<dyn Observer>>,
+) -> Result<()> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    let failed = |source: io::Error| PipelineError::Read {
+        name: proc_dir.display().to_string(),
+        source,
+    };
+    let mut watched: Vec<Watched> = open_files(&proc_dir, fd)
+        .map_err(failed)?
+        .into_iter()
+        .filter_map(|(fd, path)| watch(&proc_dir, fd, path, units).ok())
+        .collect();
+    if watched.is_empty() {
+        return Err(failed(io::Error::new(
+            ErrorKind::NotFound,
+            "no open files to watch",
+        )));
+    }
+
+    while !watched.is_empty() {
+        if let Some(signal) = signal::received() {
+            // the lines drawn so far still get finished off
+            for file in watched.iter_mut() {
+                file.stats.set_finished();
+                for observer in observers.iter_mut() {
+                    observer.finish(&file.stats);
+                }
+            }
+            return Err(PipelineError::Interrupted(signal));
+        }
+        // an exited process has nothing open anymore
+        let files = open_files(&proc_dir, fd).unwrap_or_default();
+        let mut still_open = Vec::with_capacity(watched.len());
+        for mut file in watched.drain(..) {
+            let reopened = !files.contains(&(file.fd, file.path.clone()));
+            let info = fs::read_to_string(proc_dir.join("fdinfo").join(file.fd.to_string()))
+                .ok()
+                .and_then(|text| parse_fdinfo(&text));
+            match info {
+                Some(info) if !reopened => {
+                    if info.pos >= file.pos {
+                        file.stats.update(info.pos - file.pos);
+                    } else {
+                        file.stats.seek(info.pos);
+                    }
+                    file.pos = info.pos;
+                    if file.stats.take_ready() {
+                        for observer in observers.iter_mut() {
+                            observer.update(&file.stats);
+                        }
+                    }
+                    still_open.push(file);
+                }
+                _ => {
+                    file.stats.set_finished();
+                    for observer in observers.iter_mut() {
+                        observer.finish(&file.stats);
+                    }
+                }
+            }
+        }
+        watched = still_open;
+        // files opened since the last poll, only when watching all of them
+        if fd.is_none() {
+            for (fd, path) in files {
+                if !watched.iter().any(|file| file.fd == fd) {
+                    if let Ok(file) = watch(&proc_dir, fd, path, units) {
+                        watched.push(file);
+                    }
+                }
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_fdinfo, watch_loop, FdInfo};
+    use crate::stats::Stats;
+    use crate::units::Units;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_fdinfo() {
+        let text = "pos:\t4096\nflags:\t0100001\nmnt_id:\t28\nino:\t1234\n";
+        assert_eq!(
+            parse_fdinfo(text),
+            Some(FdInfo {
+                pos: 4096,
+                write_only: true
+            })
+        );
+        assert_eq!(parse_fdinfo("flags:\t02\n"), None);
+    }
+
+    #[test]
+    fn watches_own_descriptor_until_closed() {
+        let path = std::env::temp_dir().join(format!("pipeviewer-watch-{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[0; 1000]).unwrap();
+        let fd = file.as_raw_fd() as u32;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let observer = move |stats: &Stats| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((stats.total(), stats.is_finished()))
+        };
+        let watcher = thread::spawn(move || {
+            watch_loop(
+                std::process::id(),
+                Some(fd),
+                Units::default(),
+                vec![Box::new(observer)],
+            )
+        });
+        thread::sleep(Duration::from_millis(100));
+        drop(file);
+        watcher.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(seen.lock().unwrap().last(), Some(&(1000, true)));
+    }
+}
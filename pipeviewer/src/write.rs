@@ -0,0 +1,263 @@
+use super::error::{PipelineError, Result, Warning, WarningHandler};
+use super::pool::Chunk;
+use crossbeam::channel::{bounded, Receiver, Sender};
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::thread::{self, JoinHandle};
+
+/// Messages sent from the read loop to the write loops
+#[derive(Clone)]
+pub enum WriteMessage {
+    Chunk(Chunk),
+    /// End of stream, flush and stop
+    Done,
+}
+
+// pub fn write_output(outfile: &str, buffer: &[u8]) -> IoResult<bool> {
+//     let mut writer: Box<dyn Write> = if !outfile.is_empty() {
+//         Box::new(BufWriter::new(File::create(outfile)?))
+//     } else {
+//         Box::new(BufWriter::new(io::stdout()))
+//     };
+
+//     if let Err(e) = writer.write_all(&buffer) {
+//         if e.kind() == ErrorKind::BrokenPipe {
+//             //false means "stop program cleanly"
+//             return Ok(false);
+//         }
+//         return Err(e);
+//     }
+//     // true means "keep going"
+//     Ok(true)
+// }
+
+/// A named destination for the write loop
+pub struct Output {
+    name: String,
+    writer: Box<dyn Write + Send>,
+    /// Descriptor behind `writer` for the splice fast path
+    #[cfg(unix)]
+    fd: Option<RawFd>,
+}
+
+impl Output {
+    pub fn new<W: Write + Send + 'static>(name: impl Into<String>, writer: W) -> Self {
+        Self {
+            name: name.into(),
+            writer: Box::new(writer),
+            #[cfg(unix)]
+            fd: None,
+        }
+    }
+
+    pub fn stdout() -> Self {
+        let stdout = io::stdout();
+        #[cfg(unix)]
+        let fd = Some(stdout.as_raw_fd());
+        Self {
+            #[cfg(unix)]
+            fd,
+            ..Self::new("stdout", BufWriter::new(stdout))
+        }
+    }
+
+    /// Creates (or truncates) `path`, `-` or an empty path being stdout
+    pub fn create(path: &str) -> Result<Self> {
+        if path.is_empty() || path == "-" {
+            return Ok(Self::stdout());
+        }
+        match File::create(path) {
+            Ok(file) => {
+                #[cfg(unix)]
+                let fd = Some(file.as_raw_fd());
+                Ok(Self {
+                    #[cfg(unix)]
+                    fd,
+                    ..Self::new(path, BufWriter::new(file))
+                })
+            }
+            Err(source) => Err(PipelineError::Write {
+                name: path.to_string(),
+                source,
+            }),
+        }
+    }
+
+    /// Connects to `addr`, e.g. `example.com:9000`, and sends the stream there
+    pub fn connect(addr: &str) -> Result<Self> {
+        match TcpStream::connect(addr) {
+            Ok(stream) => {
+                #[cfg(unix)]
+                let fd = Some(stream.as_raw_fd());
+                Ok(Self {
+                    #[cfg(unix)]
+                    fd,
+                    ..Self::new(format!("tcp:{}", addr), BufWriter::new(stream))
+                })
+            }
+            Err(source) => Err(PipelineError::Write {
+                name: format!("tcp:{}", addr),
+                source,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[cfg(unix)]
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+}
+
+/// Writes chunks until `Done`. A broken pipe stops the loop with `PipelineError::BrokenPipe`.
+pub fn write_loop(output: Output, write_rx: Receiver<WriteMessage>) -> Result<()> {
+    let Output {
+        name, mut writer, ..
+    } = output;
+    let failed = |source: io::Error| -> Result<()> {
+        if source.kind() == ErrorKind::BrokenPipe {
+            return Err(PipelineError::BrokenPipe(name.clone()));
+        }
+        Err(PipelineError::Write {
+            name: name.clone(),
+            source,
+        })
+    };
+
+    loop {
+        let buffer = match write_rx.recv() {
+            Ok(WriteMessage::Chunk(buffer)) => buffer,
+            Ok(WriteMessage::Done) => break,
+            Err(_) => return Err(PipelineError::Disconnected("read")),
+        };
+        if let Err(e) = writer.write_all(&buffer) {
+            return failed(e);
+        }
+    }
+    writer.flush().or_else(failed)
+}
+
+/// An output destination served by its own `write_loop` thread
+struct Sink {
+    tx: Sender<WriteMessage>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl Sink {
+    fn spawn(output: Output, queue_depth: usize) -> Self {
+        let (tx, rx) = bounded(queue_depth);
+        let handle = thread::spawn(move || write_loop(output, rx));
+        Self { tx, handle }
+    }
+
+    /// Waits for the writer thread
+    fn join(self) -> Result<()> {
+        self.handle
+            .join()
+            .unwrap_or(Err(PipelineError::Panicked("write")))
+    }
+}
+
+/// Outputs dropped with `drop_failed`, whether they failed to open or while writing.
+/// The latest failure is held back, it becomes the error once no output is left.
+pub struct Dropped {
+    latest: Option<PipelineError>,
+    warn: WarningHandler,
+}
+
+impl Dropped {
+    pub fn new(warn: WarningHandler) -> Self {
+        Self { latest: None, warn }
+    }
+
+    /// Drops an output that failed with `error`, reporting the one held back before
+    pub fn push(&mut self, error: PipelineError) {
+        if let Some(previous) = self.latest.replace(error) {
+            (self.warn)(&Warning::OutputDropped(previous));
+        }
+    }
+
+    /// Whether any output was dropped
+    pub fn any(&self) -> bool {
+        self.latest.is_some()
+    }
+
+    /// The outcome of the write stage, given whether an output made it to the end
+    pub fn finish(self, healthy: bool, result: Result<()>) -> Result<()> {
+        match self.latest {
+            // every output failed, the last failure stands for all of them
+            Some(e) if !healthy && result.is_ok() => Err(e),
+            Some(e) => {
+                (self.warn)(&Warning::OutputDropped(e));
+                result
+            }
+            None => result,
+        }
+    }
+}
+
+/// Broadcasts every chunk to all `outputs`.
+///
+/// A writer thread that exits early has failed. With `drop_failed` it joins the
+/// outputs in `dropped` and the others keep going, otherwise the whole write stage stops.
+/// Either way it is an error once no output is left.
+pub fn tee_loop(
+    outputs: Vec<Output>,
+    drop_failed: bool,
+    queue_depth: usize,
+    mut dropped: Dropped,
+    write_rx: Receiver<WriteMessage>,
+) -> Result<()> {
+    let mut sinks: Vec<Sink> = outputs
+        .into_iter()
+        .map(|output| Sink::spawn(output, queue_depth))
+        .collect();
+    let mut result = Ok(());
+    let mut finished = false;
+    let mut stopped = false;
+    while !finished && !stopped && !sinks.is_empty() {
+        let message = match write_rx.recv() {
+            Ok(message) => message,
+            Err(_) => {
+                result = Err(PipelineError::Disconnected("read"));
+                break;
+            }
+        };
+        finished = matches!(message, WriteMessage::Done);
+        let mut live = Vec::with_capacity(sinks.len());
+        for sink in sinks.drain(..) {
+            if sink.tx.send(message.clone()).is_ok() {
+                live.push(sink);
+                continue;
+            }
+            match sink.join() {
+                Err(e) if drop_failed => dropped.push(e),
+                outcome => {
+                    result = outcome;
+                    stopped = true;
+                }
+            }
+        }
+        sinks = live;
+    }
+    let mut healthy = false;
+    for sink in sinks {
+        if !finished {
+            // stopped early, tell the remaining outputs to flush and finish
+            let _ = sink.tx.send(WriteMessage::Done);
+        }
+        match sink.join() {
+            Err(e) if drop_failed => dropped.push(e),
+            Ok(()) => healthy = true,
+            outcome if result.is_ok() => result = outcome,
+            _ => (),
+        }
+    }
+    dropped.finish(healthy, result)
+}
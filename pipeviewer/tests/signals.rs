@@ -0,0 +1,73 @@
+//! SIGINT handling of the built binary in its different modes.
+#![cfg(unix)]
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+fn pipeviewer(args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_pipeviewer"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn interrupt(child: Child) -> Output {
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn interrupted_copy_exits_130_with_a_summary() {
+    let mut child = pipeviewer(&[]);
+    // once data came through, the handler is in place
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"hello\n").unwrap();
+    let mut echoed = [0; 6];
+    child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_exact(&mut echoed)
+        .unwrap();
+    assert_eq!(&echoed, b"hello\n");
+
+    let output = interrupt(child);
+    drop(stdin);
+    assert_eq!(output.status.code(), Some(130));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("6 B in 0:00:0"), "{}", stderr);
+    assert!(stderr.contains(": average "), "{}", stderr);
+}
+
+#[test]
+fn interrupted_watch_exits_130() {
+    let path = std::env::temp_dir().join(format!("pipeviewer-signals-{}", std::process::id()));
+    let file = File::create(&path).unwrap();
+    let target = format!("{}:{}", std::process::id(), file.as_raw_fd());
+    let child = pipeviewer(&["--watchfd", &target]);
+    thread::sleep(Duration::from_millis(500));
+    let output = interrupt(child);
+    drop(file);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(output.status.code(), Some(130));
+}
+
+#[test]
+fn interrupted_listen_exits_130() {
+    let child = pipeviewer(&["--listen", "127.0.0.1:0"]);
+    thread::sleep(Duration::from_millis(500));
+    let output = interrupt(child);
+    assert_eq!(output.status.code(), Some(130));
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}